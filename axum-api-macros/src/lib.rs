@@ -0,0 +1,395 @@
+mod validated;
+
+use {
+    proc_macro::TokenStream,
+    proc_macro2::{Span, TokenStream as TokenStream2},
+    quote::{format_ident, quote},
+    syn::{Attribute, Data, DeriveInput, Fields, Ident, LitInt, LitStr, Result, parse_macro_input},
+};
+
+/// Implement `IntoApiError`, `IntoResponse` and `OperationOutput` for an
+/// error enum or struct.
+///
+/// Every variant (or the struct) takes
+/// `#[api_error(status = 404, title = "User Not Found")]` and optionally
+/// `detail = "No user {id}"`, a format string over the fields (`{0}` for
+/// tuple fields), and `type = "https://..."`. Fields marked
+/// `#[api_error(extension)]` are added to the extensions, a field marked
+/// `#[api_error(source)]` becomes the source of the error.
+#[proc_macro_derive(ApiError, attributes(api_error))]
+pub fn derive_api_error(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Derive `Validate` and `JsonSchema` for a struct, with the rules of its
+/// `#[validate(..)]` attributes mirrored into the schema, e.g. `minLength`,
+/// `maximum`, `exclusiveMinimum` or `format: email`. Place it above the
+/// `#[derive(..)]` of the struct.
+///
+/// Files of a multipart form take
+/// `#[file(max_size = "2MiB", content_type = "image/png")]`, checked by
+/// `ValidateFiles` and documented as binary. `max_size` also becomes the
+/// `#[form_data(limit = "..")]` of the field, unless it has one.
+#[proc_macro_attribute]
+pub fn validated(args: TokenStream, input: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        return syn::Error::new(Span::call_site(), "`validated` takes no arguments")
+            .into_compile_error()
+            .into();
+    }
+    let input = parse_macro_input!(input as DeriveInput);
+    validated::expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+struct ErrorAttrs {
+    status: u16,
+    title: LitStr,
+    detail: Option<LitStr>,
+    type_uri: Option<LitStr>,
+}
+
+impl ErrorAttrs {
+    fn parse(attrs: &[Attribute], span: Span) -> Result<Self> {
+        let mut status = None;
+        let mut title = None;
+        let mut detail = None;
+        let mut type_uri = None;
+        for attr in attrs.iter().filter(|a| a.path().is_ident("api_error")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("status") {
+                    let lit: LitInt = meta.value()?.parse()?;
+                    let value: u16 = lit.base10_parse()?;
+                    if !(400..=599).contains(&value) {
+                        return Err(syn::Error::new(lit.span(), "expected an error status"));
+                    }
+                    status = Some(value);
+                } else if meta.path.is_ident("title") {
+                    title = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("detail") {
+                    detail = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("type") {
+                    type_uri = Some(meta.value()?.parse()?);
+                } else {
+                    return Err(meta.error("expected `status`, `title`, `detail` or `type`"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(Self {
+            status: status
+                .ok_or_else(|| syn::Error::new(span, "missing `#[api_error(status = ..)]`"))?,
+            title: title
+                .ok_or_else(|| syn::Error::new(span, "missing `#[api_error(title = ..)]`"))?,
+            detail,
+            type_uri,
+        })
+    }
+}
+
+enum FieldRole {
+    Plain,
+    Extension,
+    Source,
+}
+
+impl FieldRole {
+    fn parse(attrs: &[Attribute]) -> Result<Self> {
+        let mut role = Self::Plain;
+        for attr in attrs.iter().filter(|a| a.path().is_ident("api_error")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("extension") {
+                    role = Self::Extension;
+                } else if meta.path.is_ident("source") {
+                    role = Self::Source;
+                } else {
+                    return Err(meta.error("expected `extension` or `source`"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(role)
+    }
+}
+
+/// A variant, or the struct, mapped to one kind of error
+struct Case {
+    pattern: TokenStream2,
+    attrs: ErrorAttrs,
+    /// Binding, name in the extensions and role of every field
+    fields: Vec<(Ident, String, FieldRole)>,
+}
+
+impl Case {
+    fn new(path: TokenStream2, fields: &Fields, attrs: ErrorAttrs) -> Result<Self> {
+        let mut bound = Vec::new();
+        for (i, field) in fields.iter().enumerate() {
+            let (binding, name) = match &field.ident {
+                Some(ident) => (ident.clone(), ident.to_string()),
+                None => (format_ident!("_{}", i), i.to_string()),
+            };
+            bound.push((binding, name, FieldRole::parse(&field.attrs)?));
+        }
+        let bindings = bound.iter().map(|(binding, ..)| binding);
+        let pattern = match fields {
+            Fields::Named(_) => quote!(#path { #(#bindings),* }),
+            Fields::Unnamed(_) => quote!(#path ( #(#bindings),* )),
+            Fields::Unit => quote!(#path),
+        };
+        Ok(Self {
+            pattern,
+            attrs,
+            fields: bound,
+        })
+    }
+
+    fn arm(&self) -> TokenStream2 {
+        let Self {
+            pattern,
+            attrs,
+            fields,
+        } = self;
+        let status = attrs.status;
+        let title = &attrs.title;
+        let detail = attrs.detail.as_ref().map(|detail| {
+            // Tuple fields are bound as `_0`, `_1`.. so `{0}` becomes `{_0}`
+            let format = LitStr::new(&tuple_placeholders(&detail.value()), detail.span());
+            quote!(error.detail = ::core::option::Option::Some(::std::format!(#format));)
+        });
+        let type_uri = attrs.type_uri.as_ref().map(|uri| {
+            quote! {
+                error.r#type = ::core::option::Option::Some(
+                    ::std::string::ToString::to_string(#uri),
+                );
+            }
+        });
+        let extensions: Vec<_> = fields
+            .iter()
+            .filter(|(_, _, role)| matches!(role, FieldRole::Extension))
+            .map(|(binding, name, _)| {
+                quote! {
+                    extensions.insert(
+                        ::std::string::ToString::to_string(#name),
+                        ::axum_api::__private::serde_json::to_value(&#binding)
+                            .unwrap_or_default(),
+                    );
+                }
+            })
+            .collect();
+        let extensions = (!extensions.is_empty()).then(|| {
+            quote! {
+                let mut extensions = ::axum_api::__private::serde_json::Map::new();
+                #(#extensions)*
+                error.extensions = ::core::option::Option::Some(
+                    ::axum_api::__private::serde_json::Value::Object(extensions),
+                );
+            }
+        });
+        let source = fields
+            .iter()
+            .find(|(_, _, role)| matches!(role, FieldRole::Source))
+            .map(|(binding, ..)| {
+                quote! {
+                    error.source = ::core::option::Option::Some(::std::boxed::Box::new(#binding));
+                }
+            });
+        quote! {
+            #pattern => {
+                let mut error = ::axum_api::api_error::ApiError {
+                    status: ::axum_api::__private::StatusCode::from_u16(#status)
+                        .expect("Status is checked by the derive"),
+                    title: ::std::string::ToString::to_string(#title),
+                    ..::core::default::Default::default()
+                };
+                #detail
+                #type_uri
+                #extensions
+                #source
+                error
+            }
+        }
+    }
+}
+
+fn tuple_placeholders(format: &str) -> String {
+    let mut out = String::with_capacity(format.len());
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        out.push(c);
+        if c == '{' {
+            if chars.peek() == Some(&'{') {
+                out.push(chars.next().unwrap());
+            } else if chars.peek().is_some_and(char::is_ascii_digit) {
+                out.push('_');
+            }
+        }
+    }
+    out
+}
+
+fn expand(input: DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let cases = match &input.data {
+        Data::Enum(data) => data
+            .variants
+            .iter()
+            .map(|variant| {
+                let ident = &variant.ident;
+                let attrs = ErrorAttrs::parse(&variant.attrs, ident.span())?;
+                Case::new(quote!(Self::#ident), &variant.fields, attrs)
+            })
+            .collect::<Result<Vec<_>>>()?,
+        Data::Struct(data) => {
+            let attrs = ErrorAttrs::parse(&input.attrs, name.span())?;
+            vec![Case::new(quote!(Self), &data.fields, attrs)?]
+        }
+        Data::Union(_) => {
+            return Err(syn::Error::new(
+                name.span(),
+                "`ApiError` can't be derived for unions",
+            ));
+        }
+    };
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let arms = cases.iter().map(Case::arm);
+    let statuses = cases.iter().map(|case| case.attrs.status);
+    let titles = cases.iter().map(|case| &case.attrs.title);
+    Ok(quote! {
+        impl #impl_generics ::axum_api::api_error::IntoApiError for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn into_error_response(self) -> ::axum_api::api_error::ApiError {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+
+        impl #impl_generics ::axum_api::__private::axum::response::IntoResponse
+            for #name #ty_generics #where_clause
+        {
+            fn into_response(self) -> ::axum_api::__private::axum::response::Response {
+                ::axum_api::__private::axum::response::IntoResponse::into_response(
+                    ::axum_api::api_error::IntoApiError::into_error_response(self),
+                )
+            }
+        }
+
+        impl #impl_generics ::axum_api::__private::aide::OperationOutput
+            for #name #ty_generics #where_clause
+        {
+            type Inner = ::axum_api::api_error::ApiError;
+
+            fn operation_response(
+                ctx: &mut ::axum_api::__private::aide::generate::GenContext,
+                operation: &mut ::axum_api::__private::aide::openapi::Operation,
+            ) -> ::core::option::Option<::axum_api::__private::aide::openapi::Response> {
+                <::axum_api::api_error::ApiError as ::axum_api::__private::aide::OperationOutput>::operation_response(ctx, operation)
+            }
+
+            fn inferred_responses(
+                ctx: &mut ::axum_api::__private::aide::generate::GenContext,
+                operation: &mut ::axum_api::__private::aide::openapi::Operation,
+            ) -> ::std::vec::Vec<(
+                ::core::option::Option<::axum_api::__private::aide::openapi::StatusCode>,
+                ::axum_api::__private::aide::openapi::Response,
+            )> {
+                ::axum_api::api_error::error_responses(
+                    ctx,
+                    operation,
+                    &[#((#statuses, #titles)),*],
+                )
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, syn::parse_quote};
+
+    fn expanded(input: DeriveInput) -> String {
+        expand(input).unwrap().to_string()
+    }
+
+    #[test]
+    fn tuple_fields_are_bound_in_details() {
+        assert_eq!(
+            tuple_placeholders("No user {0}, not {{0}} nor {name}"),
+            "No user {_0}, not {{0}} nor {name}"
+        );
+    }
+
+    #[test]
+    fn variants_become_errors_and_documented_responses() {
+        let out = expanded(parse_quote! {
+            enum UserError {
+                #[api_error(status = 404, title = "User Not Found", detail = "No user {0}")]
+                NotFound(u64),
+                #[api_error(status = 409, title = "Login Taken", type = "https://example.com/taken")]
+                Taken {
+                    #[api_error(extension)]
+                    login: String,
+                    #[api_error(source)]
+                    cause: std::io::Error,
+                },
+            }
+        });
+        assert!(out.contains("Self :: NotFound (_0) =>"));
+        assert!(out.contains(r#"format ! ("No user {_0}")"#));
+        assert!(out.contains("Self :: Taken { login , cause } =>"));
+        assert!(out.contains(r#"to_string ("https://example.com/taken")"#));
+        assert!(out.contains(
+            r#"extensions . insert (:: std :: string :: ToString :: to_string ("login")"#
+        ));
+        assert!(out.contains(
+            "error . source = :: core :: option :: Option :: Some (:: std :: boxed :: Box :: new \
+             (cause))"
+        ));
+        assert!(out.contains(r#"& [(404u16 , "User Not Found") , (409u16 , "Login Taken")]"#));
+    }
+
+    #[test]
+    fn expansion_only_uses_absolute_paths() {
+        let out = expanded(parse_quote! {
+            #[api_error(status = 500, title = "Broken", detail = "{0}")]
+            struct Broken(#[api_error(extension)] String);
+        });
+        assert!(
+            !out.replace(":: core :: default :: Default", "")
+                .contains("Default")
+        );
+        assert!(!out.replace(":: Some (", "").contains("Some ("));
+        assert!(!out.replace(":: format !", "").contains("format !"));
+        assert!(!out.replace(":: Vec <", "").contains("Vec <"));
+        assert!(!out.replace(":: Option <", "").contains("Option <"));
+    }
+
+    #[test]
+    fn invalid_attributes_are_rejected() {
+        let error = |input: DeriveInput| expand(input).unwrap_err().to_string();
+        assert_eq!(
+            error(parse_quote! {
+                #[api_error(status = 200, title = "Fine")]
+                struct Fine;
+            }),
+            "expected an error status"
+        );
+        assert_eq!(
+            error(parse_quote! {
+                #[api_error(status = 404)]
+                struct Missing;
+            }),
+            "missing `#[api_error(title = ..)]`"
+        );
+        assert_eq!(
+            error(parse_quote! {
+                union Bits { a: u32, b: f32 }
+            }),
+            "`ApiError` can't be derived for unions"
+        );
+    }
+}
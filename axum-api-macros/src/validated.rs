@@ -0,0 +1,412 @@
+use {
+    proc_macro2::TokenStream as TokenStream2,
+    quote::{ToTokens, quote},
+    syn::{
+        Attribute, Data, DeriveInput, Expr, Field, GenericArgument, Lit, LitStr, Path,
+        PathArguments, Result, Token, Type, meta::ParseNestedMeta, parse_quote,
+        punctuated::Punctuated,
+    },
+};
+
+/// Rules of `#[validate(..)]` the `JsonSchema` derive doesn't mirror by
+/// itself, it already handles `length`, `range(min, max)`, `email`, `url`,
+/// `ip`, `regex`, `contains` and `required`
+#[derive(Default)]
+struct ExtraRules {
+    exclusive_min: Option<Expr>,
+    exclusive_max: Option<Expr>,
+    does_not_contain: Option<LitStr>,
+}
+
+impl ExtraRules {
+    fn parse(attrs: &[Attribute]) -> Result<Self> {
+        let mut rules = Self::default();
+        for attr in attrs.iter().filter(|a| a.path().is_ident("validate")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("range") {
+                    meta.parse_nested_meta(|item| {
+                        if item.path.is_ident("exclusive_min") {
+                            rules.exclusive_min = Some(item.value()?.parse()?);
+                        } else if item.path.is_ident("exclusive_max") {
+                            rules.exclusive_max = Some(item.value()?.parse()?);
+                        } else {
+                            skip(&item)?;
+                        }
+                        Ok(())
+                    })
+                } else if meta.path.is_ident("does_not_contain") {
+                    meta.parse_nested_meta(|item| {
+                        if item.path.is_ident("pattern") {
+                            rules.does_not_contain = Some(item.value()?.parse()?);
+                        } else {
+                            skip(&item)?;
+                        }
+                        Ok(())
+                    })
+                } else {
+                    skip(&meta)
+                }
+            })?;
+        }
+        Ok(rules)
+    }
+
+    /// `#[schemars(extend(..))]` adding the rules to the field's schema
+    fn extension(&self) -> Option<Attribute> {
+        let mut keywords = Vec::new();
+        if let Some(min) = &self.exclusive_min {
+            keywords.push(quote!("exclusiveMinimum" = #min));
+        }
+        if let Some(max) = &self.exclusive_max {
+            keywords.push(quote!("exclusiveMaximum" = #max));
+        }
+        if let Some(pattern) = &self.does_not_contain {
+            let pattern = LitStr::new(&escape_regex(&pattern.value()), pattern.span());
+            keywords.push(quote!("not" = { "pattern": #pattern }));
+        }
+        (!keywords.is_empty()).then(|| parse_quote!(#[schemars(extend(#(#keywords),*))]))
+    }
+}
+
+/// Consume the arguments of a rule with nothing to mirror
+fn skip(meta: &ParseNestedMeta) -> Result<()> {
+    if meta.input.peek(Token![=]) {
+        meta.value()?.parse::<Expr>()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        let args;
+        syn::parenthesized!(args in meta.input);
+        args.parse::<TokenStream2>()?;
+    }
+    Ok(())
+}
+
+fn escape_regex(literal: &str) -> String {
+    let mut escaped = String::with_capacity(literal.len());
+    for c in literal.chars() {
+        if r"\.+*?()|[]{}^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Rules of `#[file(..)]`, e.g.
+/// `#[file(max_size = "2MiB", content_type = "image/png")]`
+struct FileAttr {
+    max_size: Option<u64>,
+    content_types: Vec<LitStr>,
+}
+
+impl FileAttr {
+    fn parse(attr: &Attribute) -> Result<Self> {
+        let mut max_size = None;
+        let mut content_types = Vec::new();
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("max_size") {
+                max_size = Some(match meta.value()?.parse()? {
+                    Lit::Int(bytes) => bytes.base10_parse()?,
+                    Lit::Str(size) => parse_size(&size)?,
+                    lit => return Err(syn::Error::new(lit.span(), "expected a size")),
+                });
+            } else if meta.path.is_ident("content_type") {
+                content_types.push(meta.value()?.parse()?);
+            } else {
+                return Err(meta.error("expected `max_size` or `content_type`"));
+            }
+            Ok(())
+        })?;
+        Ok(Self {
+            max_size,
+            content_types,
+        })
+    }
+}
+
+/// Bytes of a size like `512KiB` or `2MB`
+fn parse_size(size: &LitStr) -> Result<u64> {
+    let value = size.value();
+    let unit_at = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(unit_at);
+    let multiplier = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1000,
+        "kib" => 1 << 10,
+        "mb" => 1000 * 1000,
+        "mib" => 1 << 20,
+        "gb" => 1000 * 1000 * 1000,
+        "gib" => 1 << 30,
+        _ => return Err(syn::Error::new(size.span(), "unknown unit of size")),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| syn::Error::new(size.span(), "expected a size like `2MiB`"))
+}
+
+/// Name of the field in the form, `#[form_data(field_name = "..")]` or its
+/// own, and whether it sets its own `#[form_data(limit = "..")]`
+fn form_data(field: &Field) -> Result<(String, bool)> {
+    let mut name = field.ident.as_ref().map(ToString::to_string);
+    let mut limited = false;
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|a| a.path().is_ident("form_data"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("field_name") {
+                name = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else {
+                limited |= meta.path.is_ident("limit");
+                skip(&meta)?;
+            }
+            Ok(())
+        })?;
+    }
+    let name =
+        name.ok_or_else(|| syn::Error::new_spanned(field, "`#[file]` needs a named field"))?;
+    Ok((name, limited))
+}
+
+/// `ty` with its files documented as `UploadedFile`, e.g.
+/// `Vec<UploadedFile>` of `Vec<FieldData<Bytes>>`
+fn file_schema(ty: &Type) -> Type {
+    if let Type::Path(path) = ty
+        && let Some(segment) = path.path.segments.last()
+        && (segment.ident == "Option" || segment.ident == "Vec")
+        && let PathArguments::AngleBracketed(args) = &segment.arguments
+        && let Some(GenericArgument::Type(inner)) = args.args.first()
+    {
+        let wrapper = &segment.ident;
+        let inner = file_schema(inner);
+        return parse_quote!(#wrapper<#inner>);
+    }
+    parse_quote!(::axum_api::extractors::UploadedFile)
+}
+
+/// `ValidateFiles` of the fields marked `#[file(..)]`, the attributes removed,
+/// if there are any. Their `max_size` also limits the field while the form is
+/// read, so larger files aren't buffered whole.
+fn validate_files(input: &mut DeriveInput) -> Result<Option<TokenStream2>> {
+    let Data::Struct(data) = &mut input.data else {
+        unreachable!("checked by `expand`");
+    };
+    let mut rules = Vec::new();
+    let mut checks = Vec::new();
+    for field in data.fields.iter_mut() {
+        let Some(index) = field.attrs.iter().position(|a| a.path().is_ident("file")) else {
+            continue;
+        };
+        let attr = field.attrs.remove(index);
+        let FileAttr {
+            max_size,
+            content_types,
+        } = FileAttr::parse(&attr)?;
+        if !field.attrs.iter().any(|a| a.path().is_ident("schemars")) {
+            let schema = file_schema(&field.ty).to_token_stream().to_string();
+            field.attrs.push(parse_quote!(#[schemars(with = #schema)]));
+        }
+        let (name, limited) = form_data(field)?;
+        if let Some(max_size) = max_size.filter(|_| !limited) {
+            let limit = max_size.to_string();
+            field.attrs.push(parse_quote!(#[form_data(limit = #limit)]));
+        }
+        let ident = &field.ident;
+        let max_size = match max_size {
+            Some(max_size) => quote!(::std::option::Option::Some(#max_size)),
+            None => quote!(::std::option::Option::None),
+        };
+        let rule = quote!(::axum_api::extractors::FileRule {
+            max_size: #max_size,
+            content_types: &[#(#content_types),*],
+        });
+        checks.push(quote!(#rule.check(#name, &self.#ident, &mut errors);));
+        rules.push(quote!((#name, #rule)));
+    }
+    if rules.is_empty() {
+        return Ok(None);
+    }
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(Some(quote! {
+        impl #impl_generics ::axum_api::extractors::ValidateFiles for #name #ty_generics #where_clause {
+            fn file_rules() -> ::std::vec::Vec<(&'static str, ::axum_api::extractors::FileRule)> {
+                ::std::vec![#(#rules),*]
+            }
+
+            fn validate_files(
+                &self,
+            ) -> ::std::result::Result<(), ::axum_api::__private::validator::ValidationErrors> {
+                let mut errors = ::axum_api::__private::validator::ValidationErrors::new();
+                #(#checks)*
+                if errors.is_empty() {
+                    ::std::result::Result::Ok(())
+                } else {
+                    ::std::result::Result::Err(errors)
+                }
+            }
+        }
+    }))
+}
+
+/// Whether `name` is already derived, e.g. `JsonSchema` or
+/// `schemars::JsonSchema`
+fn derives(attrs: &[Attribute], name: &str) -> Result<bool> {
+    for attr in attrs.iter().filter(|a| a.path().is_ident("derive")) {
+        let paths = attr.parse_args_with(Punctuated::<Path, Token![,]>::parse_terminated)?;
+        if paths
+            .iter()
+            .any(|path| path.segments.last().is_some_and(|s| s.ident == name))
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Whether the struct already sets `#[validate(crate = "..")]`
+fn names_validator_crate(attrs: &[Attribute]) -> Result<bool> {
+    let mut named = false;
+    for attr in attrs.iter().filter(|a| a.path().is_ident("validate")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("crate") {
+                named = true;
+            }
+            skip(&meta)
+        })?;
+    }
+    Ok(named)
+}
+
+pub(crate) fn expand(mut input: DeriveInput) -> Result<TokenStream2> {
+    let Data::Struct(data) = &mut input.data else {
+        return Err(syn::Error::new(
+            input.ident.span(),
+            "`validated` only applies to structs",
+        ));
+    };
+    for field in data.fields.iter_mut() {
+        let extension = ExtraRules::parse(&field.attrs)?.extension();
+        field.attrs.extend(extension);
+    }
+    let validate_files = validate_files(&mut input)?;
+    let mut derived = Vec::new();
+    if !derives(&input.attrs, "Validate")? {
+        derived.push(quote!(::axum_api::__private::validator::Validate));
+    }
+    // So users don't need to depend on `validator` themselves
+    let validator_crate = (!names_validator_crate(&input.attrs)?)
+        .then(|| quote!(#[validate(crate = "::axum_api::__private::validator")]));
+    let schema_crate = if derives(&input.attrs, "JsonSchema")? {
+        None
+    } else {
+        derived.push(quote!(::axum_api::__private::schemars::JsonSchema));
+        Some(quote!(#[schemars(crate = "::axum_api::__private::schemars")]))
+    };
+    let derive = (!derived.is_empty()).then(|| quote!(#[derive(#(#derived),*)]));
+    Ok(quote! {
+        #derive
+        #schema_crate
+        #validator_crate
+        #input
+        #validate_files
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, proc_macro2::Span};
+
+    fn size(size: &str) -> Result<u64> {
+        parse_size(&LitStr::new(size, Span::call_site()))
+    }
+
+    fn schema(ty: Type) -> String {
+        file_schema(&ty).to_token_stream().to_string()
+    }
+
+    #[test]
+    fn sizes_are_read_in_bytes() {
+        assert_eq!(size("512").unwrap(), 512);
+        assert_eq!(size("512b").unwrap(), 512);
+        assert_eq!(size("2kB").unwrap(), 2000);
+        assert_eq!(size("2KiB").unwrap(), 2048);
+        assert_eq!(size("2 MiB").unwrap(), 2 << 20);
+        assert_eq!(size("3MB").unwrap(), 3_000_000);
+        assert_eq!(size("1GiB").unwrap(), 1 << 30);
+    }
+
+    #[test]
+    fn malformed_sizes_are_rejected() {
+        assert_eq!(
+            size("2PiB").unwrap_err().to_string(),
+            "unknown unit of size"
+        );
+        assert_eq!(
+            size("MiB").unwrap_err().to_string(),
+            "expected a size like `2MiB`"
+        );
+        assert_eq!(
+            size("").unwrap_err().to_string(),
+            "expected a size like `2MiB`"
+        );
+        assert_eq!(
+            size("18446744073709551615GiB").unwrap_err().to_string(),
+            "expected a size like `2MiB`"
+        );
+    }
+
+    #[test]
+    fn files_are_documented_as_uploaded_files() {
+        let uploaded = ":: axum_api :: extractors :: UploadedFile";
+        assert_eq!(schema(parse_quote!(FieldData<Bytes>)), uploaded);
+        assert_eq!(
+            schema(parse_quote!(Option<FieldData<Bytes>>)),
+            format!("Option < {uploaded} >")
+        );
+        assert_eq!(
+            schema(parse_quote!(std::vec::Vec<FieldData<NamedTempFile>>)),
+            format!("Vec < {uploaded} >")
+        );
+        assert_eq!(
+            schema(parse_quote!(Option<Vec<FieldData<Bytes>>>)),
+            format!("Option < Vec < {uploaded} > >")
+        );
+    }
+
+    #[test]
+    fn file_sizes_limit_the_fields_they_do_not_limit_already() {
+        let out = expand(parse_quote! {
+            struct Avatar {
+                #[file(max_size = "2KiB", content_type = "image/png")]
+                image: FieldData<Bytes>,
+                #[form_data(limit = "1MiB")]
+                #[file(max_size = 1024)]
+                thumbnail: FieldData<Bytes>,
+            }
+        })
+        .unwrap()
+        .to_string();
+        assert!(out.contains(r#"# [form_data (limit = "2048")] image"#));
+        assert!(!out.contains(r#"limit = "1024""#));
+        assert!(out.contains("impl :: axum_api :: extractors :: ValidateFiles for Avatar"));
+        assert!(!out.contains("# [file"));
+    }
+
+    #[test]
+    fn forms_without_files_do_not_implement_validate_files() {
+        let out = expand(parse_quote! {
+            struct Login {
+                #[validate(length(min = 1))]
+                login: String,
+            }
+        })
+        .unwrap()
+        .to_string();
+        assert!(!out.contains("ValidateFiles"));
+    }
+}
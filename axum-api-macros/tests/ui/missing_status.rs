@@ -0,0 +1,11 @@
+use axum_api_macros::ApiError;
+
+#[derive(ApiError)]
+enum UserError {
+    #[api_error(status = 404, title = "User Not Found")]
+    NotFound,
+    #[api_error(title = "Login Taken")]
+    Taken,
+}
+
+fn main() {}
@@ -0,0 +1,7 @@
+use axum_api_macros::ApiError;
+
+#[derive(ApiError)]
+#[api_error(status = 302, title = "Moved")]
+struct Moved;
+
+fn main() {}
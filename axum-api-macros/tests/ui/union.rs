@@ -0,0 +1,9 @@
+use axum_api_macros::ApiError;
+
+#[derive(ApiError)]
+union Bits {
+    int: u32,
+    float: f32,
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use axum_api_macros::ApiError;
+
+#[derive(ApiError)]
+#[api_error(status = 400, title = "Bad Input")]
+struct BadInput {
+    #[api_error(header)]
+    field: String,
+}
+
+fn main() {}
@@ -1,41 +1,110 @@
+mod catalogue;
+mod deserialize;
+mod validation;
+
+pub(crate) use deserialize::{from_form, from_value, json_error};
 use {
-    aide::OperationOutput,
+    aide::{
+        OperationOutput,
+        openapi::{Example, ReferenceOr, SchemaObject},
+    },
     axum::{
         Json,
         extract::{
+            Request, State,
             multipart::MultipartError,
+            path::ErrorKind,
             rejection::{JsonRejection, PathRejection, QueryRejection},
         },
-        http::StatusCode,
-        response::IntoResponse,
+        http::{
+            HeaderMap, HeaderName, HeaderValue, StatusCode,
+            header::{ACCEPT, CONTENT_TYPE},
+        },
+        middleware::Next,
+        response::{IntoResponse, Response},
     },
+    axum_tracing_opentelemetry::tracing_opentelemetry_instrumentation_sdk,
     axum_typed_multipart::TypedMultipartError,
+    indexmap::{IndexMap, map::Entry},
     schemars::JsonSchema,
     serde::Serialize,
     serde_json::json,
     serde_with::{FromInto, serde_as},
+    std::sync::Arc,
+    tracing_opentelemetry::OpenTelemetrySpanExt,
+    uuid::Uuid,
     validator::ValidationErrors,
 };
+pub use {
+    catalogue::{ErrorCatalogue, ErrorType},
+    validation::{FieldError, field_errors},
+};
 
+/// Content type of [`ApiError`] responses
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Header carrying the id of the request, taken from the client if plain or
+/// else generated
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    static REQUEST: RequestContext;
+}
+
+#[derive(Clone)]
+struct RequestContext {
+    catalogue: Option<Arc<ErrorCatalogue>>,
+    path: String,
+    request_id: String,
+    accept: Option<String>,
+    #[cfg(feature = "i18n")]
+    locale: Option<Arc<crate::i18n::RequestLocale>>,
+}
+
+/// Problem details (RFC 9457) returned by every failing route
 #[serde_as]
 #[derive(Debug, Serialize, JsonSchema)]
 pub struct ApiError {
+    /// URI of the error type, `about:blank` when absent
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub r#type: Option<String>,
     #[serde_as(as = "FromInto<u16>")]
     pub status: StatusCode,
     pub title: String,
+    /// Stable key of the title, e.g. `token-expired`, for clients translating
+    /// errors themselves
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub detail: Option<String>,
+    /// URI of this occurrence, the request path unless set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
     #[serde(flatten, skip_serializing_if = "Option::is_none")]
     pub extensions: Option<serde_json::Value>,
+    /// Sent along the response, e.g. `Retry-After`, see
+    /// [`ApiError::with_header`]
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub headers: HeaderMap,
+    /// Cause of the error, logged for server errors but never sent
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub source: Option<Box<dyn std::error::Error + Send + Sync>>,
 }
 
 impl Default for ApiError {
     fn default() -> Self {
         Self {
+            r#type: Default::default(),
             status: StatusCode::INTERNAL_SERVER_ERROR,
             title: Default::default(),
+            code: Default::default(),
             detail: Default::default(),
+            instance: Default::default(),
             extensions: Default::default(),
+            headers: Default::default(),
+            source: Default::default(),
         }
     }
 }
@@ -54,11 +123,167 @@ where
 }
 
 impl IntoResponse for ApiError {
-    fn into_response(self) -> axum::response::Response {
-        (self.status, Json(self)).into_response()
+    fn into_response(mut self) -> axum::response::Response {
+        if self.code.is_none() {
+            self.code = Some(message_key(&self.title));
+        }
+        let request = REQUEST.try_with(Clone::clone).ok();
+        if self.r#type.is_none() {
+            let catalogue = request.as_ref().and_then(|r| r.catalogue.as_ref());
+            self.r#type = catalogue.and_then(|c| c.type_of(self.code.as_deref()?));
+        }
+        #[cfg(feature = "i18n")]
+        if let Some(locale) = request.as_ref().and_then(|r| r.locale.as_ref()?.get()) {
+            let key = self.code.as_deref().unwrap_or_default();
+            if let Some(title) = crate::i18n::translate(locale, key, None) {
+                self.title = title;
+            }
+        }
+        if self.instance.is_none() {
+            self.instance = request.as_ref().map(|r| r.path.clone());
+        }
+        self.add_identifiers(request.map(|r| r.request_id));
+        if self.status.is_server_error() {
+            self.log();
+        }
+        let headers = std::mem::take(&mut self.headers);
+        let content_type = [(CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON))];
+        (self.status, headers, content_type, Json(self)).into_response()
+    }
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, title: impl Into<String>) -> Self {
+        Self {
+            status,
+            title: title.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Send `name` along the response, e.g. `Retry-After`
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Attach the cause of the error, logged along server errors
+    pub fn with_source(
+        mut self,
+        source: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+    ) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    /// 500 hiding `source` from the client, except in debug builds
+    fn internal(source: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        ApiError {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            title: "Internal Server Error".to_string(),
+            detail: cfg!(debug_assertions).then(|| source.to_string()),
+            source: Some(source),
+            ..Default::default()
+        }
+    }
+
+    /// Add the trace and request ids to the extensions, so reported errors
+    /// can be found in the traces
+    fn add_identifiers(&mut self, request_id: Option<String>) {
+        let trace_id = tracing_opentelemetry_instrumentation_sdk::find_current_trace_id();
+        if trace_id.is_none() && request_id.is_none() {
+            return;
+        }
+        let extensions = self.extensions.get_or_insert_with(|| json!({}));
+        if let Some(extensions) = extensions.as_object_mut() {
+            if let Some(trace_id) = trace_id {
+                extensions.insert("trace_id".to_string(), trace_id.into());
+            }
+            if let Some(request_id) = request_id {
+                extensions.insert("request_id".to_string(), request_id.into());
+            }
+        }
+    }
+
+    fn log(&self) {
+        let mut causes = Vec::new();
+        let mut source = self.source.as_deref().map(|e| e as &dyn std::error::Error);
+        while let Some(err) = source {
+            causes.push(err.to_string());
+            source = err.source();
+        }
+        let error_type = self.r#type.as_deref().unwrap_or(&self.title);
+        tracing::Span::current().set_attribute("error.type", error_type.to_string());
+        tracing::error!(
+            status = self.status.as_u16(),
+            title = %self.title,
+            detail = ?self.detail,
+            exception.message = causes.first().map(String::as_str),
+            ?causes,
+            "Server error"
+        );
     }
 }
 
+/// Stable key of an error title, e.g. `token-expired` for `Token Expired`
+pub fn message_key(title: &str) -> String {
+    title
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// `Accept` of the request being handled, for responses negotiating their
+/// format
+pub(crate) fn request_accept() -> Option<String> {
+    REQUEST.try_with(|r| r.accept.clone()).ok().flatten()
+}
+
+/// Longest request id taken from the client
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// The request id sent by the client, if a token short and plain enough to be
+/// echoed in headers and logged
+fn client_request_id(headers: &HeaderMap) -> Option<String> {
+    let request_id = headers.get(REQUEST_ID_HEADER)?.to_str().ok()?;
+    let plain = (1..=MAX_REQUEST_ID_LEN).contains(&request_id.len())
+        && request_id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'));
+    plain.then(|| request_id.to_string())
+}
+
+/// Keep the server's error catalogue, and the request path, id and locale for
+/// the `type`, `instance`, `request_id` and translation of errors raised
+/// handling it, and its `Accept` for negotiated responses. Echo the request id
+/// in the response
+pub(crate) async fn problem_context(
+    State(catalogue): State<Option<Arc<ErrorCatalogue>>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let request_id = client_request_id(req.headers()).unwrap_or_else(|| Uuid::new_v4().to_string());
+    let context = RequestContext {
+        catalogue,
+        path: req.uri().path().to_string(),
+        request_id: request_id.clone(),
+        accept: req
+            .headers()
+            .get(ACCEPT)
+            .and_then(|h| h.to_str().ok())
+            .map(str::to_string),
+        #[cfg(feature = "i18n")]
+        locale: crate::i18n::RequestLocale::of(&req).map(Arc::new),
+    };
+    let mut res = REQUEST.scope(context, next.run(req)).await;
+    if let Ok(request_id) = HeaderValue::from_str(&request_id) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+    }
+    res
+}
+
 impl OperationOutput for ApiError {
     type Inner = Json<Self>;
 
@@ -66,7 +291,147 @@ impl OperationOutput for ApiError {
         ctx: &mut aide::generate::GenContext,
         operation: &mut aide::openapi::Operation,
     ) -> Option<aide::openapi::Response> {
-        Self::Inner::operation_response(ctx, operation)
+        let mut res = Self::Inner::operation_response(ctx, operation)?;
+        if let Some(media) = res.content.shift_remove("application/json") {
+            res.content.insert(PROBLEM_JSON.to_string(), media);
+        }
+        Some(res)
+    }
+
+    /// Any error, documented as the default response of the operation
+    fn inferred_responses(
+        ctx: &mut aide::generate::GenContext,
+        operation: &mut aide::openapi::Operation,
+    ) -> Vec<(Option<aide::openapi::StatusCode>, aide::openapi::Response)> {
+        Self::operation_response(ctx, operation)
+            .map(|mut res| {
+                res.description = "Error".to_string();
+                (None, res)
+            })
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Result of handlers, `E` documents its possible errors in OpenAPI when it
+/// derives [`ApiError`](crate::ApiError)
+pub type ApiResult<T, E = ApiError> = Result<T, E>;
+
+/// Error of a status and title documented with [`error_responses`], for
+/// rejections declaring their error next to its construction
+pub(crate) fn documented_error((status, title): (u16, &str)) -> ApiError {
+    ApiError::new(
+        StatusCode::from_u16(status).expect("Documented errors have a valid status"),
+        title,
+    )
+}
+
+/// Responses documenting errors of these statuses and titles, errors sharing
+/// a status are examples of the same response
+pub fn error_responses(
+    ctx: &mut aide::generate::GenContext,
+    operation: &mut aide::openapi::Operation,
+    errors: &[(u16, &str)],
+) -> Vec<(Option<aide::openapi::StatusCode>, aide::openapi::Response)> {
+    let mut responses = IndexMap::<u16, aide::openapi::Response>::new();
+    for &(status, title) in errors {
+        let res = match responses.entry(status) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let Some(mut res) = ApiError::operation_response(ctx, operation) else {
+                    continue;
+                };
+                res.description = String::new();
+                entry.insert(res)
+            }
+        };
+        if !res.description.is_empty() {
+            res.description.push_str(", ");
+        }
+        res.description.push_str(title);
+        if let Some(media) = res.content.get_mut(PROBLEM_JSON) {
+            media.examples.insert(
+                title.to_string(),
+                ReferenceOr::Item(Example {
+                    summary: Some(title.to_string()),
+                    value: Some(json!({
+                        "status": status,
+                        "title": title,
+                        "code": message_key(title),
+                    })),
+                    ..Default::default()
+                }),
+            );
+        }
+    }
+    responses
+        .into_iter()
+        .map(|(status, res)| (Some(aide::openapi::StatusCode::Code(status)), res))
+        .collect()
+}
+
+/// Responses of [`error_responses`] for extractors of the input, their 400
+/// and 422 document the failing fields as `errors`
+pub fn input_error_responses(
+    ctx: &mut aide::generate::GenContext,
+    operation: &mut aide::openapi::Operation,
+    errors: &[(u16, &str)],
+) -> Vec<(Option<aide::openapi::StatusCode>, aide::openapi::Response)> {
+    let mut responses = error_responses(ctx, operation, errors);
+    let json_schema = ctx.schema.subschema_for::<validation::ValidationProblem>();
+    for (status, res) in &mut responses {
+        if !matches!(status, Some(aide::openapi::StatusCode::Code(400 | 422))) {
+            continue;
+        }
+        let Some(media) = res.content.get_mut(PROBLEM_JSON) else {
+            continue;
+        };
+        media.schema = Some(SchemaObject {
+            json_schema: json_schema.clone(),
+            external_docs: None,
+            example: None,
+        });
+        if let Some(ReferenceOr::Item(Example {
+            value: Some(value), ..
+        })) = media.examples.get_mut("Validation error")
+        {
+            value["errors"] = json!([FieldError::new(["address", "zip"], "length")
+                .message("Must be 5 characters")
+                .param("min", 5)
+                .param("max", 5)]);
+        }
+    }
+    responses
+}
+
+/// Add `responses` to those of `operation`, keeping existing ones
+pub(crate) fn add_responses(
+    operation: &mut aide::openapi::Operation,
+    responses: Vec<(Option<aide::openapi::StatusCode>, aide::openapi::Response)>,
+) {
+    let existing = &mut operation.responses.get_or_insert_default().responses;
+    for (status, res) in responses {
+        if let Some(status) = status {
+            existing
+                .entry(status)
+                .or_insert_with(|| ReferenceOr::Item(res));
+        }
+    }
+}
+
+impl IntoApiError for eyre::Report {
+    fn into_error_response(self) -> ApiError {
+        ApiError::internal(self.into())
+    }
+}
+
+/// Opt-in conversion of any error into a 500, e.g. `.map_err(Internal)?`
+#[derive(Debug)]
+pub struct Internal<E>(pub E);
+
+impl<E: std::error::Error + Send + Sync + 'static> IntoApiError for Internal<E> {
+    fn into_error_response(self) -> ApiError {
+        ApiError::internal(Box::new(self.0))
     }
 }
 
@@ -76,54 +441,114 @@ impl IntoApiError for JsonRejection {
             status: self.status(),
             title: "Axum Json Rejection".to_string(),
             detail: Some(self.body_text()),
-            extensions: None,
+            ..Default::default()
         }
     }
 }
 
 impl IntoApiError for ValidationErrors {
     fn into_error_response(self) -> ApiError {
-        ApiError {
-            status: StatusCode::BAD_REQUEST,
-            title: "Validation error".to_string(),
-            detail: None,
-            extensions: Some(json!({
-                "validations": self
-            })),
-        }
+        ApiError::validation(field_errors(&localized(self)))
     }
 }
 
+/// `errors` translated in the locale of the request being handled
+#[cfg(feature = "i18n")]
+fn localized(mut errors: ValidationErrors) -> ValidationErrors {
+    let _ = REQUEST.try_with(|r| {
+        if let Some(locale) = r.locale.as_ref().and_then(|l| l.get()) {
+            crate::i18n::localize_validation(&mut errors, locale);
+        }
+    });
+    errors
+}
+
+#[cfg(not(feature = "i18n"))]
+fn localized(errors: ValidationErrors) -> ValidationErrors {
+    errors
+}
+
 impl IntoApiError for QueryRejection {
     fn into_error_response(self) -> ApiError {
         ApiError {
             status: self.status(),
             title: "Axum Query Rejection".to_string(),
             detail: Some(self.body_text()),
-            extensions: None,
+            ..Default::default()
         }
     }
 }
 
 impl IntoApiError for PathRejection {
     fn into_error_response(self) -> ApiError {
+        let errors = match &self {
+            PathRejection::FailedToDeserializePathParams(err) => match err.kind() {
+                ErrorKind::ParseErrorAtKey {
+                    key, expected_type, ..
+                } => {
+                    vec![FieldError::new([key.as_str()], "type").param("expected", *expected_type)]
+                }
+                ErrorKind::ParseErrorAtIndex {
+                    index,
+                    expected_type,
+                    ..
+                } => vec![
+                    FieldError::new([index.to_string().as_str()], "type")
+                        .param("expected", *expected_type),
+                ],
+                ErrorKind::DeserializeError { key, message, .. } => {
+                    vec![FieldError::new([key.as_str()], "invalid").message(message)]
+                }
+                _ => Vec::new(),
+            },
+            _ => Vec::new(),
+        };
         ApiError {
             status: self.status(),
             title: "Axum Path Rejection".to_string(),
             detail: Some(self.body_text()),
-            extensions: None,
+            ..Default::default()
         }
+        .with_field_errors(errors)
     }
 }
 
 impl IntoApiError for TypedMultipartError {
     fn into_error_response(self) -> ApiError {
+        let error = match &self {
+            TypedMultipartError::MissingField { field_name } => {
+                Some(FieldError::new([field_name.as_str()], "required"))
+            }
+            TypedMultipartError::WrongFieldType {
+                field_name,
+                wanted_type,
+                ..
+            } => Some(
+                FieldError::new([field_name.as_str()], "type")
+                    .param("expected", wanted_type.as_str()),
+            ),
+            TypedMultipartError::DuplicateField { field_name } => {
+                Some(FieldError::new([field_name.as_str()], "duplicate"))
+            }
+            TypedMultipartError::UnknownField { field_name } => {
+                Some(FieldError::new([field_name.as_str()], "unknown"))
+            }
+            TypedMultipartError::FieldTooLarge {
+                field_name,
+                limit_bytes,
+            } => Some(
+                FieldError::new([field_name.as_str()], "too_large")
+                    .param("limit_bytes", *limit_bytes),
+            ),
+            _ => None,
+        };
         ApiError {
             status: self.get_status(),
             title: "Multipart Parse Rejection".to_string(),
             detail: Some(self.to_string()),
-            extensions: None,
+            ..Default::default()
         }
+        .with_field_errors(error.into_iter().collect())
     }
 }
 
@@ -133,7 +558,43 @@ impl IntoApiError for MultipartError {
             status: self.status(),
             title: "Multipart Parse Rejection".to_string(),
             detail: Some(self.to_string()),
-            extensions: None,
+            ..Default::default()
+        }
+    }
+}
+
+impl IntoApiError for diesel::result::Error {
+    fn into_error_response(self) -> ApiError {
+        ApiError {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            title: "Database Error".to_string(),
+            source: Some(Box::new(self)),
+            ..Default::default()
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_id(value: &str) -> Option<String> {
+        let mut headers = HeaderMap::new();
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_str(value).unwrap());
+        client_request_id(&headers)
+    }
+
+    #[test]
+    fn only_plain_request_ids_are_taken_from_clients() {
+        assert_eq!(
+            request_id("req_01H8.abc-9").as_deref(),
+            Some("req_01H8.abc-9")
+        );
+        assert_eq!(request_id(""), None);
+        assert_eq!(request_id("id\" forged=\"1"), None);
+        assert_eq!(request_id("id%0d%0aSet-Cookie"), None);
+        assert_eq!(request_id(&"a".repeat(129)), None);
+        assert!(request_id(&"a".repeat(128)).is_some());
+        assert_eq!(client_request_id(&HeaderMap::new()), None);
+    }
+}
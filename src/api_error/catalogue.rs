@@ -0,0 +1,178 @@
+use {
+    super::{ApiError, message_key},
+    aide::openapi::{OpenApi, SchemaObject},
+    axum::http::StatusCode,
+    schemars::{JsonSchema, Schema, SchemaGenerator, json_schema},
+};
+
+/// A kind of error with a stable URI, see [`ErrorCatalogue`]
+#[derive(Debug, Clone)]
+pub struct ErrorType {
+    pub uri: String,
+    pub status: StatusCode,
+    pub title: String,
+    /// [`ApiError::code`] of the errors of this type, by default the
+    /// [`message_key`] of the title
+    pub code: String,
+    pub description: Option<String>,
+    extensions: Option<fn(&mut SchemaGenerator) -> Schema>,
+}
+
+impl ErrorType {
+    pub fn new(uri: impl Into<String>, status: StatusCode, title: impl Into<String>) -> Self {
+        let title = title.into();
+        Self {
+            uri: uri.into(),
+            status,
+            code: message_key(&title),
+            title,
+            description: None,
+            extensions: None,
+        }
+    }
+
+    /// Match errors with this code rather than the one of the title
+    pub fn code(mut self, code: impl Into<String>) -> Self {
+        self.code = code.into();
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Document the members added by [`ApiError::extensions`] to errors of
+    /// this type
+    pub fn extensions<T: JsonSchema>(mut self) -> Self {
+        self.extensions = Some(|generator| generator.subschema_for::<T>());
+        self
+    }
+
+    /// An error of this type
+    pub fn error(&self) -> ApiError {
+        ApiError {
+            r#type: Some(self.uri.clone()),
+            status: self.status,
+            title: self.title.clone(),
+            code: Some(self.code.clone()),
+            ..Default::default()
+        }
+    }
+
+    /// Name of the schema in the OpenAPI components, e.g. `TokenExpiredProblem`
+    fn schema_name(&self) -> String {
+        let name: String = self
+            .title
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .map(|word| {
+                let mut chars = word.chars();
+                chars
+                    .next()
+                    .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+                    .unwrap_or_default()
+            })
+            .collect();
+        name + "Problem"
+    }
+
+    fn schema(&self, generator: &mut SchemaGenerator) -> Schema {
+        let mut all_of = vec![
+            generator.subschema_for::<ApiError>(),
+            json_schema!({
+                "type": "object",
+                "required": ["type"],
+                "properties": {
+                    "type": { "const": self.uri },
+                    "status": { "const": self.status.as_u16() },
+                    "title": { "const": self.title },
+                    "code": { "const": self.code },
+                },
+            }),
+        ];
+        if let Some(extensions) = self.extensions {
+            all_of.push(extensions(generator));
+        }
+        let mut schema = json_schema!({ "allOf": all_of });
+        if let Some(description) = &self.description {
+            schema.insert("description".to_string(), description.clone().into());
+        }
+        schema
+    }
+}
+
+/// Error types with stable URIs. Errors with the code of a registered type
+/// are answered with its URI as `type`, and every type is documented as a
+/// schema of the generated OpenAPI.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorCatalogue {
+    types: Vec<ErrorType>,
+}
+
+impl ErrorCatalogue {
+    pub fn register(mut self, error_type: ErrorType) -> Self {
+        self.types.push(error_type);
+        self
+    }
+
+    /// Add the schema of every type to the components of `api`
+    pub(crate) fn document(&self, api: &mut OpenApi) {
+        let schemas = aide::generate::in_context(|ctx| {
+            self.types
+                .iter()
+                .map(|t| (t.schema_name(), t.schema(&mut ctx.schema)))
+                .collect::<Vec<_>>()
+        });
+        let components = api.components.get_or_insert_default();
+        for (name, json_schema) in schemas {
+            components.schemas.insert(
+                name,
+                SchemaObject {
+                    json_schema,
+                    external_docs: None,
+                    example: None,
+                },
+            );
+        }
+    }
+
+    /// URI of the registered type with this code
+    pub(crate) fn type_of(&self, code: &str) -> Option<String> {
+        self.types
+            .iter()
+            .find(|t| t.code == code)
+            .map(|t| t.uri.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn types_are_found_by_code() {
+        let catalogue = ErrorCatalogue::default()
+            .register(ErrorType::new(
+                "https://errors.example.com/token-expired",
+                StatusCode::UNAUTHORIZED,
+                "Token Expired",
+            ))
+            .register(
+                ErrorType::new(
+                    "https://errors.example.com/quota",
+                    StatusCode::TOO_MANY_REQUESTS,
+                    "Quota Exceeded",
+                )
+                .code("quota"),
+            );
+        assert_eq!(
+            catalogue.type_of("token-expired").as_deref(),
+            Some("https://errors.example.com/token-expired")
+        );
+        assert_eq!(
+            catalogue.type_of("quota").as_deref(),
+            Some("https://errors.example.com/quota")
+        );
+        assert_eq!(catalogue.type_of("quota-exceeded"), None);
+    }
+}
@@ -0,0 +1,359 @@
+use {
+    super::{ApiError, FieldError, IntoApiError},
+    axum::http::StatusCode,
+    serde::de::{
+        self, DeserializeOwned, Deserializer, Expected, IntoDeserializer, Unexpected, Visitor,
+        value::MapDeserializer,
+    },
+    serde_json::error::Category,
+    serde_path_to_error::Segment,
+    serde_value::{Value, ValueDeserializer},
+    std::{borrow::Cow, fmt},
+};
+
+/// Failure of deserializing the input, raised by serde through the
+/// [`de::Error`] constructors so its code doesn't depend on the wording of
+/// messages. The rejected value isn't kept, it's the client's own input, and
+/// possibly a secret.
+#[derive(Debug)]
+pub(crate) enum Failure {
+    Type {
+        expected: String,
+    },
+    Value {
+        expected: String,
+    },
+    Length {
+        expected: String,
+    },
+    Variant {
+        expected: &'static [&'static str],
+    },
+    UnknownField,
+    Duplicate(&'static str),
+    Missing(&'static str),
+    /// Raised by the `Deserialize` of the field's type, e.g. a date
+    Custom(String),
+}
+
+impl Failure {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Type { .. } => "type",
+            Self::Value { .. } | Self::Custom(_) => "invalid",
+            Self::Length { .. } => "length",
+            Self::Variant { .. } => "variant",
+            Self::UnknownField => "unknown",
+            Self::Duplicate(_) => "duplicate",
+            Self::Missing(_) => "required",
+        }
+    }
+
+    fn expected(&self) -> Option<String> {
+        match self {
+            Self::Type { expected } | Self::Value { expected } | Self::Length { expected } => {
+                Some(expected.clone())
+            }
+            Self::Variant { expected } => Some(one_of(expected)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Type { expected } => write!(f, "invalid type, expected {expected}"),
+            Self::Value { expected } => write!(f, "invalid value, expected {expected}"),
+            Self::Length { expected } => write!(f, "invalid length, expected {expected}"),
+            Self::Variant { expected } => {
+                write!(f, "unknown variant, expected {}", one_of(expected))
+            }
+            Self::UnknownField => f.write_str("unknown field"),
+            Self::Duplicate(field) => write!(f, "duplicate field `{field}`"),
+            Self::Missing(field) => write!(f, "missing field `{field}`"),
+            Self::Custom(message) => f.write_str(without_value(message)),
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
+
+impl de::Error for Failure {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        Self::Custom(message.to_string())
+    }
+
+    fn invalid_type(_: Unexpected, expected: &dyn Expected) -> Self {
+        Self::Type {
+            expected: expected.to_string(),
+        }
+    }
+
+    fn invalid_value(_: Unexpected, expected: &dyn Expected) -> Self {
+        Self::Value {
+            expected: expected.to_string(),
+        }
+    }
+
+    fn invalid_length(_: usize, expected: &dyn Expected) -> Self {
+        Self::Length {
+            expected: expected.to_string(),
+        }
+    }
+
+    fn unknown_variant(_: &str, expected: &'static [&'static str]) -> Self {
+        Self::Variant { expected }
+    }
+
+    fn unknown_field(_: &str, _: &'static [&'static str]) -> Self {
+        Self::UnknownField
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        Self::Missing(field)
+    }
+
+    fn duplicate_field(field: &'static str) -> Self {
+        Self::Duplicate(field)
+    }
+}
+
+fn one_of(names: &[&str]) -> String {
+    let names: Vec<_> = names.iter().map(|name| format!("`{name}`")).collect();
+    format!("one of {}", names.join(", "))
+}
+
+/// `message` up to the value quoted after a colon, e.g. `invalid type` of
+/// `invalid type: string "hunter2"`
+fn without_value(message: &str) -> &str {
+    message.split_once(':').map_or(message, |(kind, _)| kind)
+}
+
+/// Failure of the field at the tracked `path`
+fn field_error(error: serde_path_to_error::Error<Failure>) -> FieldError {
+    let mut path = tracked_path(error.path());
+    let failure = error.into_inner();
+    // Unknown fields are already tracked in the path, missing ones aren't
+    if let Failure::Missing(field) = failure {
+        path.push(field.to_string());
+    }
+    let error = FieldError::new(path.iter().map(String::as_str), failure.code())
+        .message(failure.to_string());
+    match failure.expected() {
+        Some(expected) => error.param("expected", expected),
+        None => error,
+    }
+}
+
+/// Segments of a path tracked while deserializing
+fn tracked_path(path: &serde_path_to_error::Path) -> Vec<String> {
+    path.iter()
+        .filter_map(|segment| match segment {
+            Segment::Seq { index } => Some(index.to_string()),
+            Segment::Map { key } => Some(key.clone()),
+            Segment::Enum { variant } => Some(variant.clone()),
+            Segment::Unknown => None,
+        })
+        .collect()
+}
+
+/// `T` of a self-describing value, e.g. decoded from MessagePack
+pub(crate) fn from_value<T: DeserializeOwned>(value: Value) -> Result<T, FieldError> {
+    serde_path_to_error::deserialize(ValueDeserializer::<Failure>::new(value)).map_err(field_error)
+}
+
+/// `T` of an `application/x-www-form-urlencoded` input, e.g. a query string
+pub(crate) fn from_form<T: DeserializeOwned>(input: &[u8]) -> Result<T, FieldError> {
+    let pairs = form_urlencoded::parse(input).map(|(name, value)| (name, FormValue(value)));
+    serde_path_to_error::deserialize(MapDeserializer::<_, Failure>::new(pairs)).map_err(field_error)
+}
+
+/// Value of a form, parsed as the type asks
+struct FormValue<'de>(Cow<'de, str>);
+
+impl<'de> IntoDeserializer<'de, Failure> for FormValue<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+macro_rules! parse_value {
+    ($($method:ident => $visit:ident,)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Failure> {
+            match self.0.parse() {
+                Ok(value) => visitor.$visit(value),
+                Err(_) => Err(de::Error::invalid_type(Unexpected::Str(&self.0), &visitor)),
+            }
+        }
+    )*};
+}
+
+impl<'de> Deserializer<'de> for FormValue<'de> {
+    type Error = Failure;
+
+    parse_value! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+    }
+
+    serde::forward_to_deserialize_any! {
+        i128 u128 char str string bytes byte_buf unit unit_struct seq tuple tuple_struct map
+        struct identifier ignored_any
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Failure> {
+        match self.0 {
+            Cow::Borrowed(value) => visitor.visit_borrowed_str(value),
+            Cow::Owned(value) => visitor.visit_string(value),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Failure> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Failure> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Failure> {
+        visitor.visit_enum(self.0.into_deserializer())
+    }
+}
+
+impl IntoApiError for serde_path_to_error::Error<serde_json::Error> {
+    fn into_error_response(self) -> ApiError {
+        let path = tracked_path(self.path());
+        let err = self.into_inner();
+        let code = match err.classify() {
+            Category::Data => "invalid",
+            _ => "syntax",
+        };
+        let error =
+            FieldError::new(path.iter().map(String::as_str), code).message(json_message(&err));
+        json_rejection(&err, error)
+    }
+}
+
+/// 400 for malformed JSON, 422 for JSON not matching `T`, with the failing
+/// field, its expected type and the position of the failure
+pub(crate) fn json_error<T: DeserializeOwned>(bytes: &[u8], err: serde_json::Error) -> ApiError {
+    let error = match err.classify() {
+        // The errors of serde_json are only text, the failure is typed by
+        // deserializing the JSON again as a value
+        Category::Data => serde_json::from_slice(bytes)
+            .ok()
+            .and_then(|value| from_value::<T>(value).err())
+            .unwrap_or_else(|| FieldError::new([], "invalid").message(json_message(&err))),
+        _ => FieldError::new([], "syntax").message(json_message(&err)),
+    };
+    json_rejection(&err, error)
+}
+
+/// Message of `err` without its position, reported as params
+fn json_message(err: &serde_json::Error) -> String {
+    let message = err.to_string();
+    let position = format!(" at line {} column {}", err.line(), err.column());
+    without_value(message.strip_suffix(&position).unwrap_or(&message)).to_string()
+}
+
+fn json_rejection(err: &serde_json::Error, mut error: FieldError) -> ApiError {
+    let (status, detail) = match err.classify() {
+        Category::Data => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Failed to deserialize the JSON body into the target type",
+        ),
+        _ => (
+            StatusCode::BAD_REQUEST,
+            "Failed to parse the request body as JSON",
+        ),
+    };
+    let detail = format!("{detail}: {}", error.message.as_deref().unwrap_or_default());
+    if err.line() != 0 {
+        error = error
+            .param("line", err.line())
+            .param("column", err.column());
+    }
+    ApiError {
+        status,
+        title: "Axum Json Rejection".to_string(),
+        detail: Some(detail),
+        ..Default::default()
+    }
+    .with_field_errors(vec![error])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, serde::Deserialize)]
+    #[allow(dead_code)]
+    struct Login {
+        login: String,
+        pin: u32,
+        remember: Option<bool>,
+    }
+
+    fn json_failure(body: &str) -> serde_json::Value {
+        let err = serde_json::from_str::<Login>(body).unwrap_err();
+        let problem = json_error::<Login>(body.as_bytes(), err);
+        problem.extensions.unwrap()["errors"][0].clone()
+    }
+
+    #[test]
+    fn rejected_values_are_not_echoed() {
+        let error = json_failure(r#"{"login": "a", "pin": "hunter2, expected u8"}"#);
+        assert_eq!(error["pointer"], "/pin");
+        assert_eq!(error["code"], "type");
+        assert_eq!(error["message"], "invalid type, expected u32");
+        assert_eq!(error["params"]["expected"], "u32");
+        assert_eq!(error["params"]["line"], 1);
+
+        let error = json_failure(r#"{"login": "a", "pin": -7}"#);
+        assert_eq!(error["code"], "invalid");
+        assert!(!error["message"].as_str().unwrap().contains("-7"));
+    }
+
+    #[test]
+    fn missing_fields_are_named() {
+        let error = json_failure(r#"{"login": "a"}"#);
+        assert_eq!(error["pointer"], "/pin");
+        assert_eq!(error["code"], "required");
+        assert_eq!(error["message"], "missing field `pin`");
+    }
+
+    #[test]
+    fn form_values_parse_to_field_types() {
+        let login: Login = from_form(b"login=a%20b&pin=42&remember=true").unwrap();
+        assert_eq!(login.login, "a b");
+        assert_eq!(login.pin, 42);
+        assert_eq!(login.remember, Some(true));
+
+        let error = from_form::<Login>(b"login=a&pin=hunter2").unwrap_err();
+        assert_eq!(error.pointer, "/pin");
+        assert_eq!(error.code, "type");
+        assert_eq!(error.message.as_deref(), Some("invalid type, expected u32"));
+    }
+}
@@ -0,0 +1,162 @@
+use {
+    super::ApiError,
+    axum::http::StatusCode,
+    schemars::JsonSchema,
+    serde::Serialize,
+    serde_json::{Map, Value, json},
+    validator::{ValidationErrors, ValidationErrorsKind},
+};
+
+/// Failure of one field of the input
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct FieldError {
+    /// JSON pointer to the field, e.g. `/address/zip`, empty for the whole
+    /// input. Failures of [`Validate`](validator::Validate) point at the Rust
+    /// name of the field, see [`field_errors`].
+    pub pointer: String,
+    /// Stable code of the failure, e.g. `length`
+    pub code: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Arguments of the failed rule, e.g. `min` and `max` of `length`
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    pub params: Map<String, Value>,
+}
+
+impl FieldError {
+    /// Failure of the field at `path`, e.g. `["address", "zip"]`
+    pub fn new<'a>(path: impl IntoIterator<Item = &'a str>, code: impl Into<String>) -> Self {
+        Self {
+            pointer: path
+                .into_iter()
+                .map(|segment| format!("/{}", escape(segment)))
+                .collect(),
+            code: code.into(),
+            message: None,
+            params: Map::new(),
+        }
+    }
+
+    pub fn message(mut self, message: impl Into<String>) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
+    pub fn param(mut self, name: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.params.insert(name.into(), value.into());
+        self
+    }
+}
+
+/// Schema of problem details of rejected input, only documented
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+pub(crate) struct ValidationProblem {
+    #[serde(flatten)]
+    problem: ApiError,
+    #[serde(default)]
+    errors: Vec<FieldError>,
+}
+
+impl ApiError {
+    /// 400 listing the failing fields of the input
+    pub fn validation(errors: Vec<FieldError>) -> Self {
+        ApiError {
+            status: StatusCode::BAD_REQUEST,
+            title: "Validation error".to_string(),
+            ..Default::default()
+        }
+        .with_field_errors(errors)
+    }
+
+    /// Add `errors` to the extensions, so the client can tell which fields of
+    /// the input to fix
+    pub fn with_field_errors(mut self, errors: Vec<FieldError>) -> Self {
+        if errors.is_empty() {
+            return self;
+        }
+        let extensions = self.extensions.get_or_insert_with(|| json!({}));
+        if let Some(extensions) = extensions.as_object_mut() {
+            extensions.insert("errors".to_string(), json!(errors));
+        }
+        self
+    }
+}
+
+/// Failures of `errors` flattened, ordered by pointer.
+///
+/// `validator` names fields as they're declared in Rust, so the pointers
+/// don't follow `#[serde(rename = "..")]` or `#[serde(rename_all = "..")]`:
+/// a failure of `first_name` points at `/first_name` even if the client sent
+/// `firstName`. Keep the names of validated fields as the client sends them,
+/// or build the [`FieldError`]s by hand.
+pub fn field_errors(errors: &ValidationErrors) -> Vec<FieldError> {
+    let mut flat = Vec::new();
+    flatten(errors, "", &mut flat);
+    flat.sort_by(|a, b| a.pointer.cmp(&b.pointer));
+    flat
+}
+
+fn flatten(errors: &ValidationErrors, pointer: &str, flat: &mut Vec<FieldError>) {
+    for (field, kind) in errors.errors() {
+        // Failures of `#[validate(schema(..))]` belong to the struct itself
+        let pointer = match field.as_ref() {
+            "__all__" => pointer.to_string(),
+            field => format!("{pointer}/{}", escape(field)),
+        };
+        match kind {
+            ValidationErrorsKind::Field(failures) => {
+                flat.extend(failures.iter().map(|failure| {
+                    FieldError {
+                        pointer: pointer.clone(),
+                        code: failure.code.to_string(),
+                        message: failure.message.as_ref().map(ToString::to_string),
+                        // The rejected `value` is the client's own input, and
+                        // possibly a secret
+                        params: failure
+                            .params
+                            .iter()
+                            .filter(|(name, _)| *name != "value")
+                            .map(|(name, value)| (name.to_string(), value.clone()))
+                            .collect(),
+                    }
+                }))
+            }
+            ValidationErrorsKind::Struct(nested) => flatten(nested, &pointer, flat),
+            ValidationErrorsKind::List(items) => {
+                for (index, nested) in items {
+                    flatten(nested, &format!("{pointer}/{index}"), flat);
+                }
+            }
+        }
+    }
+}
+
+/// Escape a JSON pointer segment (RFC 6901)
+fn escape(segment: &str) -> String {
+    segment.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, serde::Deserialize, validator::Validate};
+
+    #[derive(Deserialize, Validate)]
+    #[serde(rename_all = "camelCase")]
+    struct Signup {
+        #[validate(length(min = 1))]
+        first_name: String,
+        #[serde(rename = "mail")]
+        #[validate(email)]
+        email: String,
+    }
+
+    #[test]
+    fn pointers_name_the_rust_fields_of_renamed_ones() {
+        let signup: Signup =
+            serde_json::from_value(json!({ "firstName": "", "mail": "nope" })).unwrap();
+        let errors = field_errors(&signup.validate().unwrap_err());
+        let pointers: Vec<_> = errors.iter().map(|e| e.pointer.as_str()).collect();
+        assert_eq!(pointers, ["/email", "/first_name"]);
+    }
+}
@@ -1 +1,32 @@
+pub mod api_key;
+pub mod cookie;
 pub mod jwt;
+#[cfg(feature = "auth-kit")]
+pub mod kit;
+#[cfg(feature = "mfa")]
+pub mod mfa;
+#[cfg(feature = "oidc")]
+pub mod oidc;
+#[cfg(feature = "auth-kit")]
+pub mod password;
+pub mod policy;
+pub mod revocation;
+pub mod roles;
+pub mod throttle;
+
+use {
+    base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD},
+    sha2::{Digest, Sha256},
+};
+
+/// URL safe random string carrying `len` bytes of entropy
+pub(crate) fn random_token(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    rand::fill(bytes.as_mut_slice());
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// URL safe SHA-256 digest, for secrets that are looked up by their hash
+pub(crate) fn sha256(value: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(value.as_bytes()))
+}
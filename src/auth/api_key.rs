@@ -0,0 +1,516 @@
+use {
+    crate::{
+        api_error::{ApiError, error_responses},
+        auth::{
+            random_token, sha256,
+            throttle::{FailureKey, TOO_MANY_FAILED_ATTEMPTS, Throttle, client_ip},
+        },
+        diesel_otel::RunQueryDsl,
+        extractors::Database,
+    },
+    aide::{
+        OperationInput,
+        openapi::{ApiKeyLocation, Response, SecurityRequirement, SecurityScheme, StatusCode},
+    },
+    async_trait::async_trait,
+    axum::{
+        extract::FromRequestParts,
+        http::{self, HeaderName},
+    },
+    chrono::{DateTime, Duration, Utc},
+    diesel::{ExpressionMethods, QueryDsl, Queryable, Selectable, SelectableHelper},
+    std::sync::Arc,
+    uuid::Uuid,
+};
+
+/// Name of the API key security scheme in the generated OpenAPI
+pub(crate) const API_KEY_SECURITY_SCHEME: &str = "Api Key";
+
+/// How stale `last_used_at` gets before a request writes it again
+const TOUCH_INTERVAL: Duration = Duration::minutes(1);
+
+/// An issued API key, the key itself is only known to its holder
+#[derive(Debug, Clone, Queryable, Selectable)]
+#[diesel(table_name = axum_api_keys)]
+pub struct ApiKeyRecord {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Storage for API keys, looked up by the SHA-256 of the key
+#[async_trait]
+pub trait ApiKeyStore: Send + Sync + 'static {
+    async fn insert(&self, record: &ApiKeyRecord, key_hash: &str) -> Result<(), ApiError>;
+
+    async fn find(&self, key_hash: &str) -> Result<Option<ApiKeyRecord>, ApiError>;
+
+    /// Record the use of a key, at most once a minute per key
+    async fn touch(&self, id: &str, used_at: DateTime<Utc>) -> Result<(), ApiError>;
+
+    async fn revoke(&self, id: &str) -> Result<(), ApiError>;
+}
+
+/// Where the server keeps API keys
+pub enum ApiKeyBackend {
+    /// Requires `pg_url`, tables are created on startup
+    Postgres,
+    Custom(Arc<dyn ApiKeyStore>),
+}
+
+pub struct ApiKeyConfig {
+    /// Header carrying the key
+    pub header: HeaderName,
+    pub backend: ApiKeyBackend,
+}
+
+impl Default for ApiKeyConfig {
+    fn default() -> Self {
+        Self {
+            header: HeaderName::from_static("x-api-key"),
+            backend: ApiKeyBackend::Postgres,
+        }
+    }
+}
+
+impl ApiKeyConfig {
+    pub(crate) async fn into_api_keys(
+        self,
+        database: Option<&Database>,
+    ) -> Result<ApiKeys, eyre::Error> {
+        let store: Arc<dyn ApiKeyStore> = match self.backend {
+            ApiKeyBackend::Postgres => {
+                let database = database
+                    .ok_or_else(|| eyre::eyre!("Postgres API key store requires `pg_url`"))?;
+                Arc::new(PgApiKeyStore::setup(database.clone()).await?)
+            }
+            ApiKeyBackend::Custom(store) => store,
+        };
+        Ok(ApiKeys {
+            header: self.header,
+            store,
+        })
+    }
+
+    pub(crate) fn security_scheme(&self) -> SecurityScheme {
+        SecurityScheme::ApiKey {
+            location: ApiKeyLocation::Header,
+            name: self.header.to_string(),
+            description: Some("API key for machine clients".to_string()),
+            extensions: Default::default(),
+        }
+    }
+}
+
+/// Handle to the configured [`ApiKeyStore`], extracted from the request to
+/// issue and revoke keys.
+#[derive(Clone)]
+pub struct ApiKeys {
+    header: HeaderName,
+    store: Arc<dyn ApiKeyStore>,
+}
+
+impl OperationInput for ApiKeys {}
+impl<S: Sync> FromRequestParts<S> for ApiKeys {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        _: &S,
+    ) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<ApiKeys>()
+            .cloned()
+            .ok_or_else(|| ApiError {
+                status: http::StatusCode::INTERNAL_SERVER_ERROR,
+                title: "API Keys Not Configured".to_string(),
+                ..Default::default()
+            })
+    }
+}
+
+impl ApiKeys {
+    /// Issue a new key, returned only this once
+    pub async fn issue(
+        &self,
+        name: impl Into<String>,
+        scopes: Vec<String>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(ApiKeyRecord, String), ApiError> {
+        let key = random_token(32);
+        let record = ApiKeyRecord {
+            id: Uuid::new_v4().to_string(),
+            name: name.into(),
+            scopes,
+            expires_at,
+            last_used_at: None,
+            created_at: Utc::now(),
+        };
+        self.store.insert(&record, &sha256(&key)).await?;
+        Ok((record, key))
+    }
+
+    pub async fn revoke(&self, id: &str) -> Result<(), ApiError> {
+        self.store.revoke(id).await
+    }
+}
+
+/// Authenticates machine clients through the configured API key header
+#[derive(Debug, Clone)]
+pub struct ApiKey(pub ApiKeyRecord);
+
+impl ApiKey {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.0.scopes.iter().any(|s| s == scope)
+    }
+
+    /// Rejects with 403 unless the key was issued with `scope`
+    pub fn require_scope(&self, scope: &str) -> Result<(), ApiError> {
+        if !self.has_scope(scope) {
+            Err(ApiError {
+                status: http::StatusCode::FORBIDDEN,
+                title: "Forbidden".to_string(),
+                detail: Some(format!("Missing scope `{scope}`")),
+                ..Default::default()
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl<S: Sync> FromRequestParts<S> for ApiKey {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let api_keys = ApiKeys::from_request_parts(parts, state).await?;
+        let unauthorized = |title: &str| ApiError {
+            status: http::StatusCode::UNAUTHORIZED,
+            title: title.to_string(),
+            ..Default::default()
+        };
+        let key = parts
+            .headers
+            .get(&api_keys.header)
+            .and_then(|h| h.to_str().ok())
+            .map(|k| k.trim())
+            .filter(|k| !k.is_empty())
+            .ok_or_else(|| unauthorized("Missing API Key"))?;
+        // Unknown keys count as failures of the client's address, so keys
+        // can't be guessed
+        let throttle = parts.extensions.get::<Throttle>().cloned();
+        let throttle = throttle.zip(client_ip(parts).map(|ip| [FailureKey::Ip(ip)]));
+        if let Some((throttle, keys)) = &throttle {
+            throttle.ensure_not_locked(keys).await?;
+        }
+        let Some(record) = api_keys.store.find(&sha256(key)).await? else {
+            if let Some((throttle, keys)) = &throttle {
+                throttle.failure(keys).await?;
+            }
+            return Err(unauthorized("Invalid API Key"));
+        };
+        let now = Utc::now();
+        if record.expires_at.is_some_and(|exp| now > exp) {
+            Err(unauthorized("API Key Expired"))?;
+        }
+        if record
+            .last_used_at
+            .is_none_or(|used_at| now - used_at >= TOUCH_INTERVAL)
+        {
+            api_keys.store.touch(&record.id, now).await?;
+        }
+        Ok(Self(record))
+    }
+}
+
+impl OperationInput for ApiKey {
+    fn operation_input(
+        _ctx: &mut aide::generate::GenContext,
+        operation: &mut aide::openapi::Operation,
+    ) {
+        if !operation
+            .security
+            .iter()
+            .any(|r| r.contains_key(API_KEY_SECURITY_SCHEME))
+        {
+            let mut requirement = SecurityRequirement::default();
+            requirement.insert(API_KEY_SECURITY_SCHEME.to_string(), Vec::new());
+            operation.security.push(requirement);
+        }
+    }
+
+    fn inferred_early_responses(
+        ctx: &mut aide::generate::GenContext,
+        operation: &mut aide::openapi::Operation,
+    ) -> Vec<(Option<StatusCode>, Response)> {
+        error_responses(
+            ctx,
+            operation,
+            &[
+                (401, "Missing API Key"),
+                (401, "Invalid API Key"),
+                (401, "API Key Expired"),
+                TOO_MANY_FAILED_ATTEMPTS,
+            ],
+        )
+    }
+}
+
+diesel::table! {
+    axum_api_keys (id) {
+        id -> Text,
+        name -> Text,
+        key_hash -> Text,
+        scopes -> Array<Text>,
+        expires_at -> Nullable<Timestamptz>,
+        last_used_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
+pub struct PgApiKeyStore {
+    database: Database,
+}
+
+impl PgApiKeyStore {
+    /// Create the API key table if missing
+    pub async fn setup(database: Database) -> Result<Self, diesel::result::Error> {
+        diesel::sql_query(
+            "CREATE TABLE IF NOT EXISTS axum_api_keys (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                key_hash TEXT NOT NULL UNIQUE,
+                scopes TEXT[] NOT NULL,
+                expires_at TIMESTAMPTZ,
+                last_used_at TIMESTAMPTZ,
+                created_at TIMESTAMPTZ NOT NULL
+            )",
+        )
+        .execute(&mut database.conn())
+        .await?;
+        Ok(Self { database })
+    }
+}
+
+#[async_trait]
+impl ApiKeyStore for PgApiKeyStore {
+    async fn insert(&self, record: &ApiKeyRecord, key_hash: &str) -> Result<(), ApiError> {
+        use axum_api_keys::dsl;
+        diesel::insert_into(dsl::axum_api_keys)
+            .values((
+                dsl::id.eq(&record.id),
+                dsl::name.eq(&record.name),
+                dsl::key_hash.eq(key_hash),
+                dsl::scopes.eq(&record.scopes),
+                dsl::expires_at.eq(record.expires_at),
+                dsl::last_used_at.eq(record.last_used_at),
+                dsl::created_at.eq(record.created_at),
+            ))
+            .execute(&mut self.database.conn())
+            .await?;
+        Ok(())
+    }
+
+    async fn find(&self, key_hash: &str) -> Result<Option<ApiKeyRecord>, ApiError> {
+        use axum_api_keys::dsl;
+        let record = dsl::axum_api_keys
+            .filter(dsl::key_hash.eq(key_hash))
+            .select(ApiKeyRecord::as_select())
+            .load(&mut self.database.conn())
+            .await?
+            .pop();
+        Ok(record)
+    }
+
+    async fn touch(&self, id: &str, used_at: DateTime<Utc>) -> Result<(), ApiError> {
+        use axum_api_keys::dsl;
+        diesel::update(dsl::axum_api_keys.filter(dsl::id.eq(id)))
+            .set(dsl::last_used_at.eq(used_at))
+            .execute(&mut self.database.conn())
+            .await?;
+        Ok(())
+    }
+
+    async fn revoke(&self, id: &str) -> Result<(), ApiError> {
+        use axum_api_keys::dsl;
+        diesel::delete(dsl::axum_api_keys.filter(dsl::id.eq(id)))
+            .execute(&mut self.database.conn())
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::auth::throttle::ThrottleConfig,
+        axum::extract::ConnectInfo,
+        std::{collections::HashMap, net::SocketAddr, sync::Mutex},
+    };
+
+    /// Records by the hash of their key
+    #[derive(Default)]
+    struct InMemoryApiKeyStore(Mutex<HashMap<String, ApiKeyRecord>>);
+
+    #[async_trait]
+    impl ApiKeyStore for InMemoryApiKeyStore {
+        async fn insert(&self, record: &ApiKeyRecord, key_hash: &str) -> Result<(), ApiError> {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(key_hash.to_string(), record.clone());
+            Ok(())
+        }
+
+        async fn find(&self, key_hash: &str) -> Result<Option<ApiKeyRecord>, ApiError> {
+            Ok(self.0.lock().unwrap().get(key_hash).cloned())
+        }
+
+        async fn touch(&self, id: &str, used_at: DateTime<Utc>) -> Result<(), ApiError> {
+            for record in self.0.lock().unwrap().values_mut() {
+                if record.id == id {
+                    record.last_used_at = Some(used_at);
+                }
+            }
+            Ok(())
+        }
+
+        async fn revoke(&self, id: &str) -> Result<(), ApiError> {
+            self.0.lock().unwrap().retain(|_, record| record.id != id);
+            Ok(())
+        }
+    }
+
+    struct Fixture {
+        api_keys: ApiKeys,
+        store: Arc<InMemoryApiKeyStore>,
+        throttle: Throttle,
+    }
+
+    impl Fixture {
+        async fn new() -> Self {
+            let store = Arc::new(InMemoryApiKeyStore::default());
+            let config = ApiKeyConfig {
+                backend: ApiKeyBackend::Custom(store.clone()),
+                ..Default::default()
+            };
+            let throttle = ThrottleConfig {
+                max_failures: 2,
+                ..Default::default()
+            };
+            Self {
+                api_keys: config.into_api_keys(None).await.unwrap(),
+                store,
+                throttle: throttle.into_throttle(None).await.unwrap(),
+            }
+        }
+
+        async fn authenticate(&self, key: &str) -> Result<ApiKey, ApiError> {
+            let (mut parts, ()) = http::Request::builder()
+                .header("x-api-key", key)
+                .extension(self.api_keys.clone())
+                .extension(self.throttle.clone())
+                .extension(ConnectInfo(SocketAddr::from(([203, 0, 113, 9], 443))))
+                .body(())
+                .unwrap()
+                .into_parts();
+            ApiKey::from_request_parts(&mut parts, &()).await
+        }
+
+        fn last_used_at(&self, id: &str) -> Option<DateTime<Utc>> {
+            let records = self.store.0.lock().unwrap();
+            let record = records.values().find(|record| record.id == id).unwrap();
+            record.last_used_at
+        }
+    }
+
+    #[tokio::test]
+    async fn valid_keys_authenticate_with_their_scopes() {
+        let fixture = Fixture::new().await;
+        let (record, key) = fixture
+            .api_keys
+            .issue("cron", vec!["reports:write".to_string()], None)
+            .await
+            .unwrap();
+        let api_key = fixture.authenticate(&key).await.unwrap();
+        assert_eq!(api_key.0.id, record.id);
+        assert!(api_key.has_scope("reports:write"));
+        assert_eq!(
+            api_key.require_scope("users:write").unwrap_err().status,
+            http::StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn revoked_and_unknown_keys_are_rejected() {
+        let fixture = Fixture::new().await;
+        let (record, key) = fixture
+            .api_keys
+            .issue("partner", Vec::new(), None)
+            .await
+            .unwrap();
+        fixture.api_keys.revoke(&record.id).await.unwrap();
+        let err = fixture.authenticate(&key).await.unwrap_err();
+        assert_eq!(err.status, http::StatusCode::UNAUTHORIZED);
+        assert_eq!(err.title, "Invalid API Key");
+
+        let err = fixture.authenticate("guessed").await.unwrap_err();
+        assert_eq!(err.title, "Invalid API Key");
+    }
+
+    #[tokio::test]
+    async fn expired_keys_are_rejected() {
+        let fixture = Fixture::new().await;
+        let expired = Utc::now() - Duration::seconds(1);
+        let (_, key) = fixture
+            .api_keys
+            .issue("partner", Vec::new(), Some(expired))
+            .await
+            .unwrap();
+        let err = fixture.authenticate(&key).await.unwrap_err();
+        assert_eq!(err.status, http::StatusCode::UNAUTHORIZED);
+        assert_eq!(err.title, "API Key Expired");
+    }
+
+    #[tokio::test]
+    async fn guessing_keys_locks_the_client_out() {
+        let fixture = Fixture::new().await;
+        let (_, key) = fixture
+            .api_keys
+            .issue("partner", Vec::new(), None)
+            .await
+            .unwrap();
+        for guess in ["guess-1", "guess-2"] {
+            let err = fixture.authenticate(guess).await.unwrap_err();
+            assert_eq!(err.status, http::StatusCode::UNAUTHORIZED);
+        }
+        let err = fixture.authenticate(&key).await.unwrap_err();
+        assert_eq!(err.status, http::StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn last_use_is_written_at_most_once_a_minute() {
+        let fixture = Fixture::new().await;
+        let (record, key) = fixture
+            .api_keys
+            .issue("cron", Vec::new(), None)
+            .await
+            .unwrap();
+        fixture.authenticate(&key).await.unwrap();
+        let first_use = fixture.last_used_at(&record.id).unwrap();
+        fixture.authenticate(&key).await.unwrap();
+        assert_eq!(fixture.last_used_at(&record.id), Some(first_use));
+
+        let long_ago = first_use - TOUCH_INTERVAL;
+        fixture.store.touch(&record.id, long_ago).await.unwrap();
+        fixture.authenticate(&key).await.unwrap();
+        assert!(fixture.last_used_at(&record.id).unwrap() > long_ago);
+    }
+}
@@ -0,0 +1,209 @@
+use {
+    crate::{
+        api_error::{ApiError, documented_error},
+        extractors::JWT_SECURITY_SCHEME,
+    },
+    aide::{
+        OperationInput,
+        openapi::{ApiKeyLocation, OpenApi, ReferenceOr, SecurityScheme},
+    },
+    axum::{
+        extract::FromRequestParts,
+        http::{self, HeaderMap, HeaderName, HeaderValue, Method, StatusCode, header::SET_COOKIE},
+        response::AppendHeaders,
+    },
+    chrono::Duration,
+    cookie::{Cookie, SameSite},
+    subtle::ConstantTimeEq,
+    uuid::Uuid,
+};
+
+/// Name of the cookie security scheme in the generated OpenAPI
+pub(crate) const COOKIE_SECURITY_SCHEME: &str = "Cookie Token";
+
+/// Rejection of cookie authenticated requests failing the CSRF check
+pub(crate) const CSRF_TOKEN_MISMATCH: (u16, &str) = (403, "CSRF Token Mismatch");
+
+/// Lets [`Claims`](crate::extractors::Claims) read the token from an HttpOnly
+/// cookie when no `Authorization` header is sent.
+///
+/// Cookie authenticated requests with unsafe methods must echo the value of
+/// the CSRF cookie in the CSRF header (double-submit).
+#[derive(Debug, Clone)]
+pub struct JwtCookie {
+    /// HttpOnly cookie holding the token
+    pub name: String,
+    /// Cookie readable by scripts holding the CSRF token
+    pub csrf_cookie: String,
+    /// Header the CSRF token is echoed in
+    pub csrf_header: HeaderName,
+    pub path: String,
+    pub secure: bool,
+    pub same_site: SameSite,
+}
+
+impl Default for JwtCookie {
+    fn default() -> Self {
+        Self {
+            name: "access_token".to_string(),
+            csrf_cookie: "csrf_token".to_string(),
+            csrf_header: HeaderName::from_static("x-csrf-token"),
+            path: "/".to_string(),
+            secure: true,
+            same_site: SameSite::Lax,
+        }
+    }
+}
+
+impl OperationInput for JwtCookie {}
+impl<S: Sync> FromRequestParts<S> for JwtCookie {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        _: &S,
+    ) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<JwtCookie>()
+            .cloned()
+            .ok_or_else(|| ApiError {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                title: "Cookie Authentication Not Configured".to_string(),
+                ..Default::default()
+            })
+    }
+}
+
+impl JwtCookie {
+    /// `Set-Cookie` headers storing `token` and a fresh CSRF token, both
+    /// expiring after `max_age`
+    pub fn set(
+        &self,
+        token: impl Into<String>,
+        max_age: Duration,
+    ) -> AppendHeaders<[(HeaderName, HeaderValue); 2]> {
+        let max_age = cookie::time::Duration::seconds(max_age.num_seconds());
+        let token = self
+            .cookie(&self.name, token.into(), true)
+            .max_age(max_age)
+            .build();
+        let csrf = self
+            .cookie(
+                &self.csrf_cookie,
+                Uuid::new_v4().simple().to_string(),
+                false,
+            )
+            .max_age(max_age)
+            .build();
+        AppendHeaders([set_cookie(&token), set_cookie(&csrf)])
+    }
+
+    /// `Set-Cookie` headers removing both cookies
+    pub fn clear(&self) -> AppendHeaders<[(HeaderName, HeaderValue); 2]> {
+        let token = self.cookie(&self.name, "", true).removal().build();
+        let csrf = self.cookie(&self.csrf_cookie, "", false).removal().build();
+        AppendHeaders([set_cookie(&token), set_cookie(&csrf)])
+    }
+
+    fn cookie<'c>(
+        &'c self,
+        name: &'c str,
+        value: impl Into<std::borrow::Cow<'c, str>>,
+        http_only: bool,
+    ) -> cookie::CookieBuilder<'c> {
+        Cookie::build((name, value))
+            .path(self.path.as_str())
+            .secure(self.secure)
+            .same_site(self.same_site)
+            .http_only(http_only)
+    }
+
+    /// The token sent in the cookie, after checking the CSRF token for unsafe
+    /// methods
+    pub(crate) fn token(&self, parts: &http::request::Parts) -> Result<Option<String>, ApiError> {
+        let Some(token) = read_cookie(&parts.headers, &self.name) else {
+            return Ok(None);
+        };
+        if !is_safe(&parts.method) {
+            let expected = read_cookie(&parts.headers, &self.csrf_cookie);
+            let sent = parts
+                .headers
+                .get(&self.csrf_header)
+                .and_then(|h| h.to_str().ok());
+            let matches = match (expected, sent) {
+                (Some(expected), Some(sent)) => {
+                    bool::from(expected.as_bytes().ct_eq(sent.as_bytes()))
+                }
+                _ => false,
+            };
+            if !matches {
+                Err(documented_error(CSRF_TOKEN_MISMATCH))?;
+            }
+        }
+        Ok(Some(token))
+    }
+
+    pub(crate) fn security_scheme(&self) -> SecurityScheme {
+        SecurityScheme::ApiKey {
+            location: ApiKeyLocation::Cookie,
+            name: self.name.clone(),
+            description: Some(format!(
+                "JWT in an HttpOnly cookie, unsafe methods must echo the `{}` cookie in the `{}` \
+                 header",
+                self.csrf_cookie, self.csrf_header
+            )),
+            extensions: Default::default(),
+        }
+    }
+}
+
+/// Offer the cookie scheme as an alternative to every JWT security requirement
+pub(crate) fn add_cookie_alternatives(api: &mut OpenApi) {
+    let Some(paths) = api.paths.as_mut() else {
+        return;
+    };
+    paths
+        .paths
+        .values_mut()
+        .filter_map(|p| match p {
+            ReferenceOr::Item(p) => Some(p),
+            _ => None,
+        })
+        .flat_map(aide::util::iter_operations_mut)
+        .for_each(|(_, operation)| {
+            let alternatives: Vec<_> = operation
+                .security
+                .iter()
+                .filter_map(|r| r.get(JWT_SECURITY_SCHEME))
+                .map(|scopes| [(COOKIE_SECURITY_SCHEME.to_string(), scopes.clone())].into())
+                .collect();
+            operation.security.extend(alternatives);
+        });
+}
+
+pub(crate) fn read_cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(http::header::COOKIE)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(Cookie::split_parse)
+        .filter_map(Result::ok)
+        .find(|c| c.name() == name)
+        .map(|c| c.value().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    )
+}
+
+fn set_cookie(cookie: &Cookie) -> (HeaderName, HeaderValue) {
+    (
+        SET_COOKIE,
+        HeaderValue::from_str(&cookie.to_string()).expect("Cookie is a valid header value"),
+    )
+}
@@ -1 +1,34 @@
+use {
+    crate::{api_error::ApiError, extractors::Jwt},
+    chrono::Duration,
+    schemars::JsonSchema,
+    serde::{Deserialize, Serialize},
+};
 
+/// Token payload identifying the authenticated user through `sub`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthSubject<T = ()> {
+    pub sub: String,
+    #[serde(flatten)]
+    pub extra: T,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TokenResponse {
+    pub access_token: String,
+    #[schemars(example = "Bearer")]
+    pub token_type: String,
+    /// Lifetime of the token in seconds
+    pub expires_in: i64,
+}
+
+impl TokenResponse {
+    /// Encode `payload` into a bearer token valid for `ttl`
+    pub fn issue<T: Serialize>(jwt: &Jwt, payload: T, ttl: Duration) -> Result<Self, ApiError> {
+        Ok(Self {
+            access_token: jwt.encode(payload, ttl)?,
+            token_type: "Bearer".to_string(),
+            expires_in: ttl.num_seconds(),
+        })
+    }
+}
@@ -0,0 +1,292 @@
+#[cfg(feature = "mfa")]
+use crate::auth::mfa::{Mfa, MfaStore};
+use {
+    crate::{
+        aide_ext::ApiRouterExt,
+        api_error::ApiError,
+        auth::{
+            jwt::{AuthSubject, TokenResponse},
+            password::{PasswordHasher, Verified},
+            revocation::Revocations,
+            throttle::{ClientIp, FailureKey, Throttle},
+        },
+        extractors::{Claims, Json, Jwt},
+    },
+    aide::axum::{ApiRouter, routing::post_with},
+    async_trait::async_trait,
+    axum::{Extension, http::StatusCode},
+    chrono::Duration,
+    schemars::JsonSchema,
+    serde::{Deserialize, Serialize},
+    std::sync::Arc,
+    validator::Validate,
+};
+
+/// A user as stored by the application
+#[derive(Debug, Clone)]
+pub struct StoredUser {
+    pub id: String,
+    /// PHC string made by [`PasswordHasher`]
+    pub password_hash: String,
+}
+
+/// Application side user lookup for the [`AuthKit`] routes
+#[async_trait]
+pub trait UserStore: Send + Sync + 'static {
+    /// Claims issued to the user alongside `sub`
+    type Claims: Serialize + Send;
+
+    async fn find_by_login(&self, login: &str) -> Result<Option<StoredUser>, ApiError>;
+
+    /// Fails if `login` is taken, with a 409 or the unique violation of the
+    /// login's column converted from `diesel` with `?`
+    async fn create(&self, login: &str, password_hash: &str) -> Result<StoredUser, ApiError>;
+
+    async fn update_password_hash(&self, id: &str, password_hash: &str) -> Result<(), ApiError>;
+
+    async fn claims(&self, id: &str) -> Result<Self::Claims, ApiError>;
+}
+
+#[derive(Debug, Deserialize, Validate, JsonSchema)]
+pub struct RegisterRequest {
+    #[validate(length(min = 1, max = 254))]
+    pub login: String,
+    #[validate(length(min = 8, max = 128))]
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize, Validate, JsonSchema)]
+pub struct LoginRequest {
+    #[validate(length(min = 1, max = 254))]
+    pub login: String,
+    #[validate(length(min = 1, max = 128))]
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Token(TokenResponse),
+    /// A second factor is needed, see `/auth/mfa/verify`
+    MfaRequired(MfaChallenge),
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct MfaChallenge {
+    /// Only accepted by `/auth/mfa/verify`
+    pub mfa_token: String,
+    /// Lifetime of the MFA token in seconds
+    pub expires_in: i64,
+}
+
+/// Mountable `/auth/register`, `/auth/login` and `/auth/logout` routes
+pub struct AuthKit<U> {
+    pub(crate) users: U,
+    hasher: PasswordHasher,
+    access_token_ttl: Duration,
+    #[cfg(feature = "mfa")]
+    pub(crate) mfa: Option<Mfa>,
+}
+
+impl<U: UserStore> AuthKit<U> {
+    pub fn new(users: U) -> Self {
+        Self {
+            users,
+            hasher: PasswordHasher::default(),
+            access_token_ttl: Duration::hours(1),
+            #[cfg(feature = "mfa")]
+            mfa: None,
+        }
+    }
+
+    pub fn hasher(mut self, hasher: PasswordHasher) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    pub fn access_token_ttl(mut self, ttl: Duration) -> Self {
+        self.access_token_ttl = ttl;
+        self
+    }
+
+    /// Require TOTP verification on login for users enrolled in `store`, and
+    /// mount the `/auth/mfa` routes
+    #[cfg(feature = "mfa")]
+    pub fn mfa(mut self, store: impl MfaStore, issuer: impl Into<String>) -> Self {
+        self.mfa = Some(Mfa::new(store, issuer));
+        self
+    }
+
+    pub fn router(self) -> ApiRouter {
+        let router = ApiRouter::new();
+        #[cfg(feature = "mfa")]
+        let router = match self.mfa {
+            Some(_) => crate::auth::mfa::routes::<U>(router),
+            None => router,
+        };
+        router
+            .api_route(
+                "/auth/register",
+                post_with(register::<U>, |o| {
+                    o.summary("Register")
+                        .description("Create an account and log into it")
+                }),
+            )
+            .api_route(
+                "/auth/login",
+                post_with(login::<U>, |o| o.summary("Log in")),
+            )
+            .api_route_with(
+                "/auth/logout",
+                post_with(logout, |o| {
+                    o.summary("Log out")
+                        .description("Revoke the token, if token revocation is configured")
+                }),
+                crate::jwt_open_api,
+            )
+            .api_tag("Auth")
+            .layer(Extension(Arc::new(self)))
+    }
+
+    pub(crate) async fn issue(&self, jwt: &Jwt, id: &str) -> Result<TokenResponse, ApiError> {
+        let payload = AuthSubject {
+            sub: id.to_string(),
+            extra: self.users.claims(id).await?,
+        };
+        TokenResponse::issue(jwt, payload, self.access_token_ttl)
+    }
+}
+
+async fn register<U: UserStore>(
+    Extension(kit): Extension<Arc<AuthKit<U>>>,
+    jwt: Jwt,
+    Json(req): Json<RegisterRequest>,
+) -> Result<Json<TokenResponse>, ApiError> {
+    let password_hash = kit.hasher.hash(&req.password).await?;
+    // Left to the store's unique constraint, a lookup first would race
+    let user = kit
+        .users
+        .create(&req.login, &password_hash)
+        .await
+        .map_err(|err| {
+            if login_taken(&err) {
+                ApiError {
+                    status: StatusCode::CONFLICT,
+                    title: "Login Taken".to_string(),
+                    ..Default::default()
+                }
+            } else {
+                err
+            }
+        })?;
+    Ok(Json(kit.issue(&jwt, &user.id).await?))
+}
+
+/// Whether [`UserStore::create`] failed on a taken login
+fn login_taken(err: &ApiError) -> bool {
+    use diesel::result::{DatabaseErrorKind, Error};
+    err.status == StatusCode::CONFLICT
+        || err
+            .source
+            .as_deref()
+            .and_then(|source| source.downcast_ref::<Error>())
+            .is_some_and(|source| {
+                matches!(
+                    source,
+                    Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)
+                )
+            })
+}
+
+async fn login<U: UserStore>(
+    Extension(kit): Extension<Arc<AuthKit<U>>>,
+    jwt: Jwt,
+    ip: Option<ClientIp>,
+    throttle: Option<Extension<Throttle>>,
+    Json(req): Json<LoginRequest>,
+) -> Result<Json<LoginResponse>, ApiError> {
+    let throttle = throttle.map(|Extension(throttle)| throttle);
+    let keys = FailureKey::attempt(ip, req.login.clone());
+    if let Some(throttle) = &throttle {
+        throttle.check(&keys).await?;
+    }
+    let Some(user) = kit.users.find_by_login(&req.login).await? else {
+        // Spend the time of a verification so unknown logins can't be told apart
+        kit.hasher.hash(&req.password).await?;
+        return Err(failed(throttle.as_ref(), &keys).await);
+    };
+    match kit
+        .hasher
+        .verify(&req.password, &user.password_hash)
+        .await?
+    {
+        None => Err(failed(throttle.as_ref(), &keys).await)?,
+        Some(Verified::NeedsRehash) => {
+            let password_hash = kit.hasher.hash(&req.password).await?;
+            kit.users
+                .update_password_hash(&user.id, &password_hash)
+                .await?;
+        }
+        Some(Verified::Ok) => (),
+    }
+    if let Some(throttle) = &throttle {
+        throttle.success(&keys).await?;
+    }
+    #[cfg(feature = "mfa")]
+    if let Some(mfa) = &kit.mfa {
+        if let Some(challenge) = mfa.challenge(&jwt, &user.id).await? {
+            return Ok(Json(LoginResponse::MfaRequired(challenge)));
+        }
+    }
+    Ok(Json(LoginResponse::Token(kit.issue(&jwt, &user.id).await?)))
+}
+
+/// Record a failed login and reject it
+async fn failed(throttle: Option<&Throttle>, keys: &[FailureKey]) -> ApiError {
+    if let Some(throttle) = throttle {
+        if let Err(err) = throttle.failure(keys).await {
+            return err;
+        }
+    }
+    ApiError {
+        status: StatusCode::UNAUTHORIZED,
+        title: "Invalid Credentials".to_string(),
+        ..Default::default()
+    }
+}
+
+async fn logout(
+    claims: Claims<AuthSubject>,
+    revocations: Option<Extension<Revocations>>,
+) -> Result<(), ApiError> {
+    if let Some(Extension(revocations)) = revocations {
+        revocations.revoke(&claims).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        diesel::result::{DatabaseErrorKind, Error},
+    };
+
+    fn database_error(kind: DatabaseErrorKind) -> ApiError {
+        Error::DatabaseError(kind, Box::new("violation".to_string())).into()
+    }
+
+    #[test]
+    fn unique_violations_are_taken_logins_only_when_creating_users() {
+        let err = database_error(DatabaseErrorKind::UniqueViolation);
+        assert_eq!(err.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(login_taken(&err));
+        assert!(!login_taken(&database_error(
+            DatabaseErrorKind::ForeignKeyViolation
+        )));
+        assert!(login_taken(&ApiError {
+            status: StatusCode::CONFLICT,
+            ..Default::default()
+        }));
+    }
+}
@@ -0,0 +1,402 @@
+use {
+    crate::{
+        api_error::ApiError,
+        auth::{
+            jwt::{AuthSubject, TokenResponse},
+            kit::{AuthKit, MfaChallenge, UserStore},
+            random_token, sha256,
+            throttle::{ClientIp, FailureKey, Throttle},
+        },
+        extractors::{Claims, Json, Jwt},
+    },
+    aide::axum::{ApiRouter, routing::post_with},
+    async_trait::async_trait,
+    axum::{Extension, http::StatusCode},
+    chrono::Duration,
+    schemars::JsonSchema,
+    serde::{Deserialize, Serialize},
+    std::{
+        sync::Arc,
+        time::{SystemTime, UNIX_EPOCH},
+    },
+    subtle::ConstantTimeEq,
+    totp_rs::{Algorithm, Secret, TOTP},
+    validator::Validate,
+};
+
+/// Purpose of the token issued between password and TOTP verification
+const MFA_PENDING: &str = "mfa_pending";
+/// Purpose of the token carrying a secret until its enrolment is confirmed
+const MFA_ENROLMENT: &str = "mfa_enrolment";
+const RECOVERY_CODES: usize = 10;
+
+/// Storage of TOTP secrets and hashed recovery codes per user
+#[async_trait]
+pub trait MfaStore: Send + Sync + 'static {
+    /// Base32 TOTP secret of an enrolled user
+    async fn totp_secret(&self, user_id: &str) -> Result<Option<String>, ApiError>;
+
+    /// Store the secret and recovery codes, replacing any previous ones and
+    /// forgetting the last accepted TOTP step
+    async fn enrol(
+        &self,
+        user_id: &str,
+        totp_secret: &str,
+        recovery_code_hashes: Vec<String>,
+    ) -> Result<(), ApiError>;
+
+    /// Record `step` as the time step of the user's last accepted TOTP code,
+    /// `false` if it isn't after the recorded one, i.e. the code is replayed.
+    /// Must be atomic, e.g. an `UPDATE .. WHERE last_step < $step`.
+    async fn accept_totp_step(&self, user_id: &str, step: u64) -> Result<bool, ApiError>;
+
+    /// Remove the recovery code with this hash, `false` if the user has none
+    async fn consume_recovery_code(&self, user_id: &str, code_hash: &str)
+    -> Result<bool, ApiError>;
+}
+
+pub(crate) struct Mfa {
+    store: Arc<dyn MfaStore>,
+    issuer: String,
+    pending_ttl: Duration,
+}
+
+impl Mfa {
+    pub(crate) fn new(store: impl MfaStore, issuer: impl Into<String>) -> Self {
+        Self {
+            store: Arc::new(store),
+            issuer: issuer.into(),
+            pending_ttl: Duration::minutes(5),
+        }
+    }
+
+    /// An MFA token if the user is enrolled
+    pub(crate) async fn challenge(
+        &self,
+        jwt: &Jwt,
+        user_id: &str,
+    ) -> Result<Option<MfaChallenge>, ApiError> {
+        if self.store.totp_secret(user_id).await?.is_none() {
+            return Ok(None);
+        }
+        let subject = AuthSubject {
+            sub: user_id.to_string(),
+            extra: (),
+        };
+        Ok(Some(MfaChallenge {
+            mfa_token: jwt.encode_purpose(subject, MFA_PENDING, self.pending_ttl)?,
+            expires_in: self.pending_ttl.num_seconds(),
+        }))
+    }
+
+    fn totp(&self, secret: Vec<u8>, account: &str) -> Result<TOTP, ApiError> {
+        // One step of skew either way tolerates clock drift of up to 30s
+        TOTP::new(
+            Algorithm::SHA1,
+            6,
+            1,
+            30,
+            secret,
+            Some(self.issuer.clone()),
+            account.to_string(),
+        )
+        .map_err(|e| {
+            tracing::error!("Invalid TOTP parameters: {:?}", e);
+            ApiError {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                title: "MFA Failure".to_string(),
+                ..Default::default()
+            }
+        })
+    }
+
+    async fn verify(&self, user_id: &str, code: &str) -> Result<bool, ApiError> {
+        let Some(secret) = self.store.totp_secret(user_id).await? else {
+            return Ok(false);
+        };
+        let secret = Secret::Encoded(secret).to_bytes().map_err(|e| {
+            tracing::error!("Stored TOTP secret is not base32: {:?}", e);
+            ApiError {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                title: "MFA Failure".to_string(),
+                ..Default::default()
+            }
+        })?;
+        let totp = self.totp(secret, user_id)?;
+        if let Some(step) = accepted_step(&totp, code) {
+            return self.store.accept_totp_step(user_id, step).await;
+        }
+        self.store
+            .consume_recovery_code(user_id, &sha256(code))
+            .await
+    }
+
+    /// [`Mfa::verify`] with the failures of `keys` limited by `throttle`
+    async fn verify_throttled(
+        &self,
+        throttle: Option<&Throttle>,
+        keys: &[FailureKey],
+        user_id: &str,
+        code: &str,
+    ) -> Result<(), ApiError> {
+        if let Some(throttle) = throttle {
+            throttle.check(keys).await?;
+        }
+        if !self.verify(user_id, code).await? {
+            if let Some(throttle) = throttle {
+                throttle.failure(keys).await?;
+            }
+            Err(invalid_code())?;
+        }
+        if let Some(throttle) = throttle {
+            throttle.success(keys).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Time step of `code` among the current one and one step either side
+fn accepted_step(totp: &TOTP, code: &str) -> Option<u64> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs() / totp.step;
+    let skew = u64::from(totp.skew);
+    (now.saturating_sub(skew)..=now + skew).find(|step| {
+        totp.generate(step * totp.step)
+            .as_bytes()
+            .ct_eq(code.as_bytes())
+            .into()
+    })
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct MfaEnrolment {
+    /// Base32 secret, for manual entry in an authenticator app
+    pub secret: String,
+    /// `otpauth://` URI, to be rendered as a QR code
+    pub otpauth_uri: String,
+    /// Pass back to `/auth/mfa/confirm` with a code
+    pub enrolment_token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingEnrolment {
+    sub: String,
+    secret: String,
+    /// Hash of the secret being replaced, the enrolment is void if it changed
+    replaces: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate, JsonSchema)]
+pub struct EnrolRequest {
+    /// TOTP or recovery code of the current enrolment, required to replace it
+    #[validate(length(min = 6, max = 64))]
+    pub current_code: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate, JsonSchema)]
+pub struct ConfirmEnrolmentRequest {
+    pub enrolment_token: String,
+    #[validate(length(equal = 6))]
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct RecoveryCodes {
+    /// Single use codes replacing a TOTP code, shown only this once
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Validate, JsonSchema)]
+pub struct VerifyRequest {
+    pub mfa_token: String,
+    /// TOTP or recovery code
+    #[validate(length(min = 6, max = 64))]
+    pub code: String,
+}
+
+pub(crate) fn routes<U: UserStore>(router: ApiRouter) -> ApiRouter {
+    router
+        .api_route_with(
+            "/auth/mfa/enrol",
+            post_with(enrol::<U>, |o| {
+                o.summary("Start TOTP enrolment").description(
+                    "Generate a TOTP secret, confirmed by `/auth/mfa/confirm`. Replacing an \
+                     enrolment takes a code of the current one.",
+                )
+            }),
+            crate::jwt_open_api,
+        )
+        .api_route_with(
+            "/auth/mfa/confirm",
+            post_with(confirm::<U>, |o| o.summary("Confirm TOTP enrolment")),
+            crate::jwt_open_api,
+        )
+        .api_route(
+            "/auth/mfa/verify",
+            post_with(verify::<U>, |o| {
+                o.summary("Verify second factor")
+                    .description("Exchange the MFA token of a login and a code for a token")
+            }),
+        )
+}
+
+fn mfa<U>(kit: &AuthKit<U>) -> &Mfa {
+    kit.mfa
+        .as_ref()
+        .expect("MFA routes are only mounted with MFA configured")
+}
+
+async fn enrol<U: UserStore>(
+    Extension(kit): Extension<Arc<AuthKit<U>>>,
+    jwt: Jwt,
+    claims: Claims<AuthSubject>,
+    throttle: Option<Extension<Throttle>>,
+    Json(req): Json<EnrolRequest>,
+) -> Result<Json<MfaEnrolment>, ApiError> {
+    let mfa = mfa(&kit);
+    // Otherwise a stolen session could swap the second factor for its own
+    let current = mfa.store.totp_secret(&claims.sub).await?;
+    if current.is_some() {
+        let code = req.current_code.ok_or_else(|| ApiError {
+            status: StatusCode::UNAUTHORIZED,
+            title: "MFA Code Required".to_string(),
+            ..Default::default()
+        })?;
+        let keys = [FailureKey::Account(claims.sub.clone())];
+        mfa.verify_throttled(throttle.as_deref(), &keys, &claims.sub, &code)
+            .await?;
+    }
+    let Secret::Raw(secret) = Secret::generate_secret() else {
+        unreachable!("Generated secrets are raw")
+    };
+    let totp = mfa.totp(secret, &claims.sub)?;
+    let pending = PendingEnrolment {
+        sub: claims.sub.clone(),
+        secret: totp.get_secret_base32(),
+        replaces: current.as_deref().map(sha256),
+    };
+    Ok(Json(MfaEnrolment {
+        secret: pending.secret.clone(),
+        otpauth_uri: totp.get_url(),
+        enrolment_token: jwt.encode_purpose(pending, MFA_ENROLMENT, Duration::minutes(10))?,
+    }))
+}
+
+async fn confirm<U: UserStore>(
+    Extension(kit): Extension<Arc<AuthKit<U>>>,
+    jwt: Jwt,
+    claims: Claims<AuthSubject>,
+    Json(req): Json<ConfirmEnrolmentRequest>,
+) -> Result<Json<RecoveryCodes>, ApiError> {
+    let mfa = mfa(&kit);
+    let pending = jwt.decode_purpose::<PendingEnrolment>(&req.enrolment_token, MFA_ENROLMENT)?;
+    if pending.sub != claims.sub {
+        Err(ApiError {
+            status: StatusCode::FORBIDDEN,
+            title: "Enrolment Of Another User".to_string(),
+            ..Default::default()
+        })?;
+    }
+    let current = mfa.store.totp_secret(&claims.sub).await?;
+    if current.as_deref().map(sha256) != pending.replaces {
+        Err(ApiError {
+            status: StatusCode::CONFLICT,
+            title: "MFA Enrolment Changed".to_string(),
+            ..Default::default()
+        })?;
+    }
+    let secret = Secret::Encoded(pending.secret.clone())
+        .to_bytes()
+        .map_err(|_| invalid_code())?;
+    let step =
+        accepted_step(&mfa.totp(secret, &claims.sub)?, &req.code).ok_or_else(invalid_code)?;
+    let recovery_codes: Vec<_> = (0..RECOVERY_CODES).map(|_| random_token(8)).collect();
+    mfa.store
+        .enrol(
+            &claims.sub,
+            &pending.secret,
+            recovery_codes.iter().map(|c| sha256(c)).collect(),
+        )
+        .await?;
+    // The confirming code can't be replayed at login
+    mfa.store.accept_totp_step(&claims.sub, step).await?;
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+async fn verify<U: UserStore>(
+    Extension(kit): Extension<Arc<AuthKit<U>>>,
+    jwt: Jwt,
+    ip: Option<ClientIp>,
+    throttle: Option<Extension<Throttle>>,
+    Json(req): Json<VerifyRequest>,
+) -> Result<Json<TokenResponse>, ApiError> {
+    let pending = jwt.decode_purpose::<AuthSubject>(&req.mfa_token, MFA_PENDING)?;
+    // Six digits are guessed quickly without limits on the attempts
+    let keys = FailureKey::attempt(ip, pending.sub.clone());
+    mfa(&kit)
+        .verify_throttled(throttle.as_deref(), &keys, &pending.sub, &req.code)
+        .await?;
+    Ok(Json(kit.issue(&jwt, &pending.sub).await?))
+}
+
+fn invalid_code() -> ApiError {
+    ApiError {
+        status: StatusCode::UNAUTHORIZED,
+        title: "Invalid MFA Code".to_string(),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::sync::Mutex};
+
+    #[derive(Default)]
+    struct MemoryStore {
+        secret: Mutex<Option<String>>,
+        last_step: Mutex<Option<u64>>,
+    }
+
+    #[async_trait]
+    impl MfaStore for MemoryStore {
+        async fn totp_secret(&self, _: &str) -> Result<Option<String>, ApiError> {
+            Ok(self.secret.lock().unwrap().clone())
+        }
+
+        async fn enrol(&self, _: &str, totp_secret: &str, _: Vec<String>) -> Result<(), ApiError> {
+            *self.secret.lock().unwrap() = Some(totp_secret.to_string());
+            *self.last_step.lock().unwrap() = None;
+            Ok(())
+        }
+
+        async fn accept_totp_step(&self, _: &str, step: u64) -> Result<bool, ApiError> {
+            let mut last_step = self.last_step.lock().unwrap();
+            if last_step.is_some_and(|last| last >= step) {
+                return Ok(false);
+            }
+            *last_step = Some(step);
+            Ok(true)
+        }
+
+        async fn consume_recovery_code(&self, _: &str, _: &str) -> Result<bool, ApiError> {
+            Ok(false)
+        }
+    }
+
+    #[tokio::test]
+    async fn totp_code_is_accepted_once() {
+        let secret = Secret::Raw(vec![7; 20]);
+        let store = MemoryStore::default();
+        store
+            .enrol("alice", &secret.to_encoded().to_string(), Vec::new())
+            .await
+            .unwrap();
+        let mfa = Mfa::new(store, "test");
+        let code = mfa
+            .totp(secret.to_bytes().unwrap(), "alice")
+            .unwrap()
+            .generate_current()
+            .unwrap();
+        assert!(mfa.verify("alice", &code).await.unwrap());
+        assert!(!mfa.verify("alice", &code).await.unwrap());
+    }
+}
@@ -0,0 +1,97 @@
+use {
+    crate::api_error::ApiError,
+    argon2::{
+        Algorithm, Argon2, Params, Version,
+        password_hash::{
+            PasswordHash, PasswordHasher as _, PasswordVerifier, SaltString, rand_core::OsRng,
+        },
+    },
+    axum::http::StatusCode,
+};
+
+/// Argon2id password hashing. Hashes made with weaker parameters than the
+/// current ones are reported by [`PasswordHasher::verify`] so they can be
+/// upgraded on login.
+#[derive(Clone)]
+pub struct PasswordHasher {
+    params: Params,
+}
+
+impl Default for PasswordHasher {
+    fn default() -> Self {
+        Self {
+            params: Params::DEFAULT,
+        }
+    }
+}
+
+/// Outcome of a successful [`PasswordHasher::verify`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verified {
+    Ok,
+    /// The password matched but its hash should be replaced
+    NeedsRehash,
+}
+
+impl PasswordHasher {
+    pub fn new(params: Params) -> Self {
+        Self { params }
+    }
+
+    fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+
+    /// PHC string of `password` with a fresh salt
+    pub async fn hash(&self, password: &str) -> Result<String, ApiError> {
+        let argon2 = self.argon2();
+        let password = password.to_string();
+        tokio::task::spawn_blocking(move || {
+            let salt = SaltString::generate(&mut OsRng);
+            argon2
+                .hash_password(password.as_bytes(), &salt)
+                .map(|hash| hash.to_string())
+        })
+        .await
+        .map_err(|e| hashing_error(e.to_string()))?
+        .map_err(|e| hashing_error(e.to_string()))
+    }
+
+    /// `None` if `password` doesn't match `hash`
+    pub async fn verify(&self, password: &str, hash: &str) -> Result<Option<Verified>, ApiError> {
+        let params = self.params.clone();
+        let password = password.to_string();
+        let hash = hash.to_string();
+        tokio::task::spawn_blocking(move || {
+            let Ok(parsed) = PasswordHash::new(&hash) else {
+                tracing::error!("Stored password hash is not a PHC string");
+                return None;
+            };
+            Argon2::default()
+                .verify_password(password.as_bytes(), &parsed)
+                .ok()?;
+            let outdated = parsed.algorithm != Algorithm::Argon2id.ident()
+                || Params::try_from(&parsed).ok().is_none_or(|p| {
+                    p.m_cost() < params.m_cost()
+                        || p.t_cost() < params.t_cost()
+                        || p.p_cost() < params.p_cost()
+                });
+            Some(if outdated {
+                Verified::NeedsRehash
+            } else {
+                Verified::Ok
+            })
+        })
+        .await
+        .map_err(|e| hashing_error(e.to_string()))
+    }
+}
+
+fn hashing_error(detail: String) -> ApiError {
+    tracing::error!("Error hashing password: {}", detail);
+    ApiError {
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        title: "Password Hashing Failure".to_string(),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,99 @@
+use {
+    crate::{
+        api_error::ApiError,
+        extractors::{Claims, Database},
+    },
+    axum::http::StatusCode,
+    std::fmt::Debug,
+    tracing_opentelemetry::OpenTelemetrySpanExt,
+};
+
+/// How a denied request is answered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DenyAs {
+    /// 403, the caller learns the resource exists
+    Forbidden,
+    /// 404, the resource is hidden from the caller
+    NotFound,
+}
+
+/// Decides whether the subject of a token may perform an action on a resource
+/// of type `R`, e.g. only edit records of their own organisation.
+pub trait Policy<R: Sync>: Send + Sync {
+    /// Reported in logs and the request span on every denial
+    const NAME: &'static str;
+    const DENY_AS: DenyAs = DenyAs::Forbidden;
+
+    /// Token payload of the subject
+    type Subject: Send + Sync;
+    type Action: Debug + Send + Sync;
+
+    fn allows(
+        &self,
+        subject: &Claims<Self::Subject>,
+        action: &Self::Action,
+        resource: &R,
+    ) -> impl Future<Output = bool> + Send;
+}
+
+/// A resource that can be loaded from the [`Database`] before authorization
+pub trait LoadResource: Sized + Send + Sync {
+    type Id: Debug + Send;
+
+    fn load(
+        database: &Database,
+        id: Self::Id,
+    ) -> impl Future<Output = Result<Option<Self>, ApiError>> + Send;
+}
+
+/// Evaluate `policy`, rejecting per [`Policy::DENY_AS`] on denial
+pub async fn authorize<P: Policy<R>, R: Sync>(
+    policy: &P,
+    subject: &Claims<P::Subject>,
+    action: &P::Action,
+    resource: &R,
+) -> Result<(), ApiError> {
+    if policy.allows(subject, action, resource).await {
+        return Ok(());
+    }
+    let span = tracing::Span::current();
+    span.set_attribute("authz.policy", P::NAME);
+    span.set_attribute("authz.decision", "deny");
+    tracing::warn!(
+        authz.policy = P::NAME,
+        authz.action = ?action,
+        "Authorization denied"
+    );
+    Err(denied(P::DENY_AS))
+}
+
+/// Load the resource `id` and evaluate `policy` on it. A missing resource is
+/// answered with 404 regardless of [`Policy::DENY_AS`].
+pub async fn load_authorized<P: Policy<R>, R: LoadResource>(
+    policy: &P,
+    subject: &Claims<P::Subject>,
+    action: &P::Action,
+    database: &Database,
+    id: R::Id,
+) -> Result<R, ApiError> {
+    let resource = R::load(database, id)
+        .await?
+        .ok_or_else(|| denied(DenyAs::NotFound))?;
+    authorize(policy, subject, action, &resource).await?;
+    Ok(resource)
+}
+
+fn denied(deny_as: DenyAs) -> ApiError {
+    match deny_as {
+        DenyAs::Forbidden => ApiError {
+            status: StatusCode::FORBIDDEN,
+            title: "Forbidden".to_string(),
+            ..Default::default()
+        },
+        DenyAs::NotFound => ApiError {
+            status: StatusCode::NOT_FOUND,
+            title: "Not Found".to_string(),
+            ..Default::default()
+        },
+    }
+}
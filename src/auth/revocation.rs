@@ -0,0 +1,489 @@
+use {
+    crate::{
+        api_error::{ApiError, documented_error},
+        diesel_otel::RunQueryDsl,
+        extractors::{Claims, Database},
+    },
+    aide::OperationInput,
+    async_trait::async_trait,
+    axum::{
+        extract::FromRequestParts,
+        http::{self, StatusCode},
+    },
+    chrono::{DateTime, SubsecRound, TimeDelta, Utc},
+    diesel::{ExpressionMethods, QueryDsl, upsert::excluded},
+    std::{
+        collections::HashMap,
+        sync::{Arc, RwLock},
+        time::Duration,
+    },
+};
+
+/// How often expired revocation entries are pruned by the server
+pub(crate) const PRUNE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Rejection of revoked tokens
+pub(crate) const TOKEN_REVOKED: (u16, &str) = (401, "Token Revoked");
+
+/// Storage for revoked tokens, consulted by the [`Claims`] extractor.
+///
+/// Tokens are revoked either one by one through their `jti`, or all at once
+/// for a subject (`sub`) issued before a given instant. Every entry carries an
+/// expiry after which it can no longer match a valid token and is pruned.
+#[async_trait]
+pub trait RevocationStore: Send + Sync + 'static {
+    async fn revoke_token(&self, jti: &str, expires_at: DateTime<Utc>) -> Result<(), ApiError>;
+
+    async fn revoke_subject(
+        &self,
+        sub: &str,
+        issued_before: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), ApiError>;
+
+    async fn is_revoked(
+        &self,
+        jti: Option<&str>,
+        sub: Option<&str>,
+        iat: DateTime<Utc>,
+    ) -> Result<bool, ApiError>;
+
+    /// Drop every entry expired at `now`
+    async fn prune(&self, now: DateTime<Utc>) -> Result<(), ApiError>;
+}
+
+/// Where the server keeps revoked tokens
+pub enum RevocationBackend {
+    Memory,
+    /// Requires `pg_url`, tables are created on startup
+    Postgres,
+    Custom(Arc<dyn RevocationStore>),
+}
+
+impl RevocationBackend {
+    pub(crate) async fn into_revocations(
+        self,
+        database: Option<&Database>,
+    ) -> Result<Revocations, eyre::Error> {
+        let store: Arc<dyn RevocationStore> = match self {
+            Self::Memory => Arc::new(InMemoryRevocationStore::default()),
+            Self::Postgres => {
+                let database = database
+                    .ok_or_else(|| eyre::eyre!("Postgres revocation store requires `pg_url`"))?;
+                Arc::new(PgRevocationStore::setup(database.clone()).await?)
+            }
+            Self::Custom(store) => store,
+        };
+        Ok(Revocations(store))
+    }
+}
+
+/// Handle to the configured [`RevocationStore`], extracted from the request.
+#[derive(Clone)]
+pub struct Revocations(Arc<dyn RevocationStore>);
+
+impl OperationInput for Revocations {}
+impl<S: Sync> FromRequestParts<S> for Revocations {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        _: &S,
+    ) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Revocations>()
+            .cloned()
+            .ok_or_else(|| ApiError {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                title: "Token Revocation Not Configured".to_string(),
+                ..Default::default()
+            })
+    }
+}
+
+impl Revocations {
+    /// Revoke a single token, it stays revoked until its own expiry
+    pub async fn revoke<T>(&self, claims: &Claims<T>) -> Result<(), ApiError> {
+        let jti = claims.jti.as_deref().ok_or_else(|| ApiError {
+            status: StatusCode::BAD_REQUEST,
+            title: "Token Not Revocable".to_string(),
+            detail: Some("Token carries no `jti` claim".to_string()),
+            ..Default::default()
+        })?;
+        self.0.revoke_token(jti, claims.exp).await
+    }
+
+    /// Revoke every token of `sub` issued before `issued_before`, truncated to
+    /// the whole second like the `iat` of tokens, so a token issued right after
+    /// in the same second stays valid.
+    ///
+    /// `expires_at` must not precede the expiry of the longest-lived of those
+    /// tokens, e.g. `issued_before` plus the longest expiration handed to
+    /// [`Jwt::encode`](crate::extractors::Jwt::encode).
+    pub async fn revoke_subject(
+        &self,
+        sub: &str,
+        issued_before: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), ApiError> {
+        self.0
+            .revoke_subject(sub, whole_seconds(issued_before), expires_at)
+            .await
+    }
+
+    pub(crate) async fn ensure_not_revoked<T>(
+        &self,
+        claims: &Claims<T>,
+        sub: Option<&str>,
+    ) -> Result<(), ApiError> {
+        if self
+            .0
+            .is_revoked(claims.jti.as_deref(), sub, claims.iat)
+            .await?
+        {
+            Err(documented_error(TOKEN_REVOKED))?;
+        }
+        Ok(())
+    }
+
+    /// Periodically prune expired entries for as long as the server runs
+    pub(crate) fn spawn_pruning(&self) {
+        let store = self.0.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(err) = store.prune(Utc::now()).await {
+                    tracing::error!("Error pruning revoked tokens: {:?}", err);
+                }
+            }
+        });
+    }
+}
+
+/// `instant` without its fraction of a second, the precision of `iat`
+fn whole_seconds(instant: DateTime<Utc>) -> DateTime<Utc> {
+    instant.trunc_subsecs(0)
+}
+
+#[derive(Default)]
+pub struct InMemoryRevocationStore {
+    tokens: RwLock<HashMap<String, DateTime<Utc>>>,
+    subjects: RwLock<HashMap<String, SubjectRevocation>>,
+}
+
+struct SubjectRevocation {
+    issued_before: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+#[async_trait]
+impl RevocationStore for InMemoryRevocationStore {
+    async fn revoke_token(&self, jti: &str, expires_at: DateTime<Utc>) -> Result<(), ApiError> {
+        self.tokens
+            .write()
+            .unwrap()
+            .insert(jti.to_string(), expires_at);
+        Ok(())
+    }
+
+    async fn revoke_subject(
+        &self,
+        sub: &str,
+        issued_before: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), ApiError> {
+        self.subjects.write().unwrap().insert(
+            sub.to_string(),
+            SubjectRevocation {
+                issued_before: whole_seconds(issued_before),
+                expires_at,
+            },
+        );
+        Ok(())
+    }
+
+    async fn is_revoked(
+        &self,
+        jti: Option<&str>,
+        sub: Option<&str>,
+        iat: DateTime<Utc>,
+    ) -> Result<bool, ApiError> {
+        // Expired entries no longer match, whether or not they were pruned yet
+        let now = Utc::now();
+        let token_revoked = jti.is_some_and(|jti| {
+            self.tokens
+                .read()
+                .unwrap()
+                .get(jti)
+                .is_some_and(|expires_at| *expires_at > now)
+        });
+        let subject_revoked = sub.is_some_and(|sub| {
+            self.subjects
+                .read()
+                .unwrap()
+                .get(sub)
+                .is_some_and(|r| iat < r.issued_before && r.expires_at > now)
+        });
+        Ok(token_revoked || subject_revoked)
+    }
+
+    async fn prune(&self, now: DateTime<Utc>) -> Result<(), ApiError> {
+        self.tokens.write().unwrap().retain(|_, exp| *exp > now);
+        self.subjects
+            .write()
+            .unwrap()
+            .retain(|_, r| r.expires_at > now);
+        Ok(())
+    }
+}
+
+diesel::table! {
+    axum_api_revoked_tokens (jti) {
+        jti -> Text,
+        expires_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    axum_api_revoked_subjects (sub) {
+        sub -> Text,
+        issued_before -> Timestamptz,
+        expires_at -> Timestamptz,
+    }
+}
+
+pub struct PgRevocationStore {
+    database: Database,
+}
+
+impl PgRevocationStore {
+    /// Create the revocation tables if missing
+    pub async fn setup(database: Database) -> Result<Self, diesel::result::Error> {
+        let mut conn = database.conn();
+        diesel::sql_query(
+            "CREATE TABLE IF NOT EXISTS axum_api_revoked_tokens (
+                jti TEXT PRIMARY KEY,
+                expires_at TIMESTAMPTZ NOT NULL
+            )",
+        )
+        .execute(&mut conn)
+        .await?;
+        diesel::sql_query(
+            "CREATE TABLE IF NOT EXISTS axum_api_revoked_subjects (
+                sub TEXT PRIMARY KEY,
+                issued_before TIMESTAMPTZ NOT NULL,
+                expires_at TIMESTAMPTZ NOT NULL
+            )",
+        )
+        .execute(&mut conn)
+        .await?;
+        Ok(Self { database })
+    }
+}
+
+#[async_trait]
+impl RevocationStore for PgRevocationStore {
+    async fn revoke_token(&self, jti: &str, expires_at: DateTime<Utc>) -> Result<(), ApiError> {
+        use axum_api_revoked_tokens::dsl;
+        diesel::insert_into(dsl::axum_api_revoked_tokens)
+            .values((dsl::jti.eq(jti), dsl::expires_at.eq(expires_at)))
+            .on_conflict(dsl::jti)
+            .do_update()
+            .set(dsl::expires_at.eq(excluded(dsl::expires_at)))
+            .execute(&mut self.database.conn())
+            .await?;
+        Ok(())
+    }
+
+    async fn revoke_subject(
+        &self,
+        sub: &str,
+        issued_before: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), ApiError> {
+        use axum_api_revoked_subjects::dsl;
+        diesel::insert_into(dsl::axum_api_revoked_subjects)
+            .values((
+                dsl::sub.eq(sub),
+                dsl::issued_before.eq(whole_seconds(issued_before)),
+                dsl::expires_at.eq(expires_at),
+            ))
+            .on_conflict(dsl::sub)
+            .do_update()
+            .set((
+                dsl::issued_before.eq(excluded(dsl::issued_before)),
+                dsl::expires_at.eq(excluded(dsl::expires_at)),
+            ))
+            .execute(&mut self.database.conn())
+            .await?;
+        Ok(())
+    }
+
+    async fn is_revoked(
+        &self,
+        jti: Option<&str>,
+        sub: Option<&str>,
+        iat: DateTime<Utc>,
+    ) -> Result<bool, ApiError> {
+        let mut conn = self.database.conn();
+        if let Some(jti) = jti {
+            use axum_api_revoked_tokens::dsl;
+            let revoked = diesel::select(diesel::dsl::exists(
+                dsl::axum_api_revoked_tokens
+                    .filter(dsl::jti.eq(jti))
+                    .filter(dsl::expires_at.gt(diesel::dsl::now)),
+            ))
+            .get_result::<bool>(&mut conn)
+            .await?;
+            if revoked {
+                return Ok(true);
+            }
+        }
+        if let Some(sub) = sub {
+            use axum_api_revoked_subjects::dsl;
+            let revoked = diesel::select(diesel::dsl::exists(
+                dsl::axum_api_revoked_subjects
+                    .filter(dsl::sub.eq(sub))
+                    // Rows stored with a fraction of a second are compared
+                    // truncated: after `iat` once a whole second later
+                    .filter(dsl::issued_before.ge(whole_seconds(iat) + TimeDelta::seconds(1)))
+                    .filter(dsl::expires_at.gt(diesel::dsl::now)),
+            ))
+            .get_result::<bool>(&mut conn)
+            .await?;
+            if revoked {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    async fn prune(&self, now: DateTime<Utc>) -> Result<(), ApiError> {
+        let mut conn = self.database.conn();
+        {
+            use axum_api_revoked_tokens::dsl;
+            diesel::delete(dsl::axum_api_revoked_tokens.filter(dsl::expires_at.le(now)))
+                .execute(&mut conn)
+                .await?;
+        }
+        {
+            use axum_api_revoked_subjects::dsl;
+            diesel::delete(dsl::axum_api_revoked_subjects.filter(dsl::expires_at.le(now)))
+                .execute(&mut conn)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, chrono::TimeZone};
+
+    #[tokio::test]
+    async fn revoked_token_matches_until_expiry() {
+        let store = InMemoryRevocationStore::default();
+        let now = Utc::now();
+        store
+            .revoke_token("live", now + TimeDelta::hours(1))
+            .await
+            .unwrap();
+        store
+            .revoke_token("expired", now - TimeDelta::seconds(1))
+            .await
+            .unwrap();
+
+        assert!(store.is_revoked(Some("live"), None, now).await.unwrap());
+        assert!(!store.is_revoked(Some("expired"), None, now).await.unwrap());
+        assert!(!store.is_revoked(Some("other"), None, now).await.unwrap());
+        assert!(!store.is_revoked(None, None, now).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn subject_revocation_matches_tokens_issued_before() {
+        let store = InMemoryRevocationStore::default();
+        let now = Utc::now();
+        store
+            .revoke_subject("alice", now, now + TimeDelta::hours(1))
+            .await
+            .unwrap();
+
+        let before = now - TimeDelta::minutes(5);
+        let after = now + TimeDelta::seconds(1);
+        assert!(store.is_revoked(None, Some("alice"), before).await.unwrap());
+        assert!(!store.is_revoked(None, Some("alice"), after).await.unwrap());
+        assert!(!store.is_revoked(None, Some("bob"), before).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn tokens_issued_in_the_second_of_a_subject_revocation_stay_valid() {
+        let store = InMemoryRevocationStore::default();
+        let revoked_at = Utc.timestamp_opt(1_700_000_000, 600_000_000).unwrap();
+        store
+            .revoke_subject("alice", revoked_at, Utc::now() + TimeDelta::hours(1))
+            .await
+            .unwrap();
+
+        // The `iat` of a token issued at .900 of the same second
+        let same_second = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let second_before = same_second - TimeDelta::seconds(1);
+        assert!(
+            !store
+                .is_revoked(None, Some("alice"), same_second)
+                .await
+                .unwrap()
+        );
+        assert!(
+            store
+                .is_revoked(None, Some("alice"), second_before)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn expired_subject_revocation_no_longer_matches_before_pruning() {
+        let store = InMemoryRevocationStore::default();
+        let now = Utc::now();
+        store
+            .revoke_subject("alice", now, now - TimeDelta::seconds(1))
+            .await
+            .unwrap();
+
+        let before = now - TimeDelta::minutes(5);
+        assert!(!store.is_revoked(None, Some("alice"), before).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn prune_drops_expired_entries_only() {
+        let store = InMemoryRevocationStore::default();
+        let now = Utc::now();
+        store
+            .revoke_token("live", now + TimeDelta::hours(1))
+            .await
+            .unwrap();
+        store
+            .revoke_token("expired", now - TimeDelta::seconds(1))
+            .await
+            .unwrap();
+        store
+            .revoke_subject("alice", now, now + TimeDelta::hours(1))
+            .await
+            .unwrap();
+        store
+            .revoke_subject("bob", now, now - TimeDelta::seconds(1))
+            .await
+            .unwrap();
+
+        store.prune(now).await.unwrap();
+
+        let tokens = store.tokens.read().unwrap();
+        assert!(tokens.contains_key("live"));
+        assert!(!tokens.contains_key("expired"));
+        let subjects = store.subjects.read().unwrap();
+        assert!(subjects.contains_key("alice"));
+        assert!(!subjects.contains_key("bob"));
+    }
+}
@@ -0,0 +1,173 @@
+use {
+    crate::{
+        api_error::{ApiError, error_responses},
+        extractors::{Claims, TOKEN_ERRORS, require_jwt_scopes},
+    },
+    aide::{
+        OperationInput,
+        openapi::{Response, StatusCode},
+    },
+    axum::{extract::FromRequestParts, http},
+    serde::{Deserialize, Deserializer},
+    std::marker::PhantomData,
+};
+
+/// A role carried in the `roles` claim, e.g.
+///
+/// ```
+/// struct Admin;
+/// impl axum_api::auth::roles::Role for Admin {
+///     const NAME: &'static str = "admin";
+/// }
+/// ```
+pub trait Role: Send + Sync {
+    const NAME: &'static str;
+}
+
+/// A permission carried in the `scope` claim, space separated or an array
+pub trait Permission: Send + Sync {
+    const NAME: &'static str;
+}
+
+/// Roles and permissions of the caller, read from the `roles` and `scope`
+/// claims of the token.
+#[derive(Debug, Default, Deserialize)]
+pub struct Authorities {
+    #[serde(default)]
+    pub roles: Vec<String>,
+    #[serde(default, rename = "scope", deserialize_with = "scopes")]
+    pub permissions: Vec<String>,
+}
+
+impl Authorities {
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|p| p == permission)
+    }
+}
+
+/// Scopes of a space separated `scope`, or of an array as some identity
+/// providers issue
+fn scopes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Scope {
+        Separated(String),
+        Listed(Vec<String>),
+    }
+    Ok(match Scope::deserialize(deserializer)? {
+        Scope::Separated(scope) => scope.split_whitespace().map(str::to_string).collect(),
+        Scope::Listed(scopes) => scopes,
+    })
+}
+
+/// Rejects with 403 unless the caller's token grants role `R`
+pub struct RequireRole<R>(PhantomData<R>);
+
+impl<S: Sync, R: Role> FromRequestParts<S> for RequireRole<R> {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let claims = Claims::<Authorities>::from_request_parts(parts, state).await?;
+        if !claims.has_role(R::NAME) {
+            Err(forbidden("role", R::NAME))?;
+        }
+        Ok(Self(PhantomData))
+    }
+}
+
+impl<R: Role> OperationInput for RequireRole<R> {
+    fn operation_input(
+        _ctx: &mut aide::generate::GenContext,
+        operation: &mut aide::openapi::Operation,
+    ) {
+        require_jwt_scopes(operation, &[R::NAME]);
+    }
+
+    fn inferred_early_responses(
+        ctx: &mut aide::generate::GenContext,
+        operation: &mut aide::openapi::Operation,
+    ) -> Vec<(Option<StatusCode>, Response)> {
+        authorization_responses(ctx, operation)
+    }
+}
+
+/// Rejects with 403 unless the caller's token grants permission `P`
+pub struct RequirePermission<P>(PhantomData<P>);
+
+impl<S: Sync, P: Permission> FromRequestParts<S> for RequirePermission<P> {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let claims = Claims::<Authorities>::from_request_parts(parts, state).await?;
+        if !claims.has_permission(P::NAME) {
+            Err(forbidden("permission", P::NAME))?;
+        }
+        Ok(Self(PhantomData))
+    }
+}
+
+impl<P: Permission> OperationInput for RequirePermission<P> {
+    fn operation_input(
+        _ctx: &mut aide::generate::GenContext,
+        operation: &mut aide::openapi::Operation,
+    ) {
+        require_jwt_scopes(operation, &[P::NAME]);
+    }
+
+    fn inferred_early_responses(
+        ctx: &mut aide::generate::GenContext,
+        operation: &mut aide::openapi::Operation,
+    ) -> Vec<(Option<StatusCode>, Response)> {
+        authorization_responses(ctx, operation)
+    }
+}
+
+fn forbidden(kind: &str, name: &str) -> ApiError {
+    ApiError {
+        status: http::StatusCode::FORBIDDEN,
+        title: "Forbidden".to_string(),
+        detail: Some(format!("Missing {kind} `{name}`")),
+        ..Default::default()
+    }
+}
+
+pub(crate) fn authorization_responses(
+    ctx: &mut aide::generate::GenContext,
+    operation: &mut aide::openapi::Operation,
+) -> Vec<(Option<StatusCode>, Response)> {
+    let errors = [TOKEN_ERRORS, &[(403, "Forbidden")]].concat();
+    error_responses(ctx, operation, &errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn authorities(claims: serde_json::Value) -> Authorities {
+        serde_json::from_value(claims).unwrap()
+    }
+
+    #[test]
+    fn scopes_are_read_space_separated_or_listed() {
+        let separated = authorities(serde_json::json!({ "scope": "read:users  write:users" }));
+        assert_eq!(separated.permissions, ["read:users", "write:users"]);
+
+        let listed = authorities(serde_json::json!({ "scope": ["read:users", "write:users"] }));
+        assert_eq!(listed.permissions, ["read:users", "write:users"]);
+        assert!(listed.has_permission("write:users"));
+
+        let missing = authorities(serde_json::json!({ "roles": ["admin"] }));
+        assert!(missing.permissions.is_empty());
+        assert!(missing.has_role("admin"));
+    }
+}
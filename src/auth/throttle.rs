@@ -0,0 +1,675 @@
+use {
+    crate::{
+        api_error::{ApiError, documented_error},
+        auth::revocation::PRUNE_INTERVAL,
+        diesel_otel::RunQueryDsl,
+        extractors::Database,
+    },
+    aide::OperationInput,
+    async_trait::async_trait,
+    axum::{
+        extract::{ConnectInfo, FromRequestParts, OptionalFromRequestParts},
+        http::{self, HeaderMap, HeaderValue, StatusCode, header::RETRY_AFTER},
+    },
+    chrono::{DateTime, Duration, Utc},
+    diesel::{
+        BoolExpressionMethods, ExpressionMethods, QueryDsl, Queryable, QueryableByName, Selectable,
+        SelectableHelper,
+        sql_types::{Text, Timestamptz},
+    },
+    std::{
+        collections::HashMap,
+        fmt,
+        net::{IpAddr, SocketAddr},
+        sync::{Arc, RwLock},
+    },
+};
+
+/// What authentication failures are counted against
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum FailureKey {
+    Ip(IpAddr),
+    /// Login or user id
+    Account(String),
+}
+
+impl FailureKey {
+    /// Keys of an attempt at `account`, and of the client's address if known
+    pub fn attempt(ip: Option<ClientIp>, account: impl Into<String>) -> Vec<Self> {
+        ip.map(|ClientIp(ip)| Self::Ip(ip))
+            .into_iter()
+            .chain([Self::Account(account.into())])
+            .collect()
+    }
+}
+
+impl fmt::Display for FailureKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ip(ip) => write!(f, "ip:{ip}"),
+            Self::Account(account) => write!(f, "account:{account}"),
+        }
+    }
+}
+
+/// Failures recorded for a key
+#[derive(Debug, Clone, Queryable, QueryableByName, Selectable)]
+#[diesel(table_name = axum_api_auth_failures)]
+pub struct FailureEntry {
+    /// Consecutive failures, each less than `window` after the previous one
+    pub failures: i32,
+    pub last_failure_at: DateTime<Utc>,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+/// Storage for authentication failures, consulted by [`Throttle`]
+#[async_trait]
+pub trait FailureStore: Send + Sync + 'static {
+    async fn get(&self, key: &str) -> Result<Option<FailureEntry>, ApiError>;
+
+    /// Count a failure at `now`, starting over from one if the previous
+    /// failure precedes `window_start`
+    async fn record_failure(
+        &self,
+        key: &str,
+        now: DateTime<Utc>,
+        window_start: DateTime<Utc>,
+    ) -> Result<FailureEntry, ApiError>;
+
+    async fn lock(&self, key: &str, until: DateTime<Utc>) -> Result<(), ApiError>;
+
+    async fn clear(&self, key: &str) -> Result<(), ApiError>;
+
+    /// Drop every entry whose last failure precedes `window_start` and which
+    /// isn't locked at `now`
+    async fn prune(&self, window_start: DateTime<Utc>, now: DateTime<Utc>) -> Result<(), ApiError>;
+}
+
+/// Where the server keeps authentication failures
+pub enum FailureBackend {
+    Memory,
+    /// Requires `pg_url`, tables are created on startup
+    Postgres,
+    Custom(Arc<dyn FailureStore>),
+}
+
+/// Brute-force protection of [`Claims`](crate::extractors::Claims) and the
+/// login routes.
+///
+/// Every failure slows down further attempts of its keys by `delay_step`, up
+/// to `max_delay`. After `max_failures` failures a key is locked out for
+/// `lockout`, its attempts are then answered with 429 and `Retry-After`.
+pub struct ThrottleConfig {
+    pub max_failures: i32,
+    /// Failures further apart than this don't add up
+    pub window: Duration,
+    pub lockout: Duration,
+    pub delay_step: Duration,
+    pub max_delay: Duration,
+    pub backend: FailureBackend,
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        Self {
+            max_failures: 5,
+            window: Duration::minutes(15),
+            lockout: Duration::minutes(15),
+            delay_step: Duration::milliseconds(250),
+            max_delay: Duration::seconds(3),
+            backend: FailureBackend::Memory,
+        }
+    }
+}
+
+impl ThrottleConfig {
+    pub(crate) async fn into_throttle(
+        self,
+        database: Option<&Database>,
+    ) -> Result<Throttle, eyre::Error> {
+        let store: Arc<dyn FailureStore> = match self.backend {
+            FailureBackend::Memory => Arc::new(InMemoryFailureStore::default()),
+            FailureBackend::Postgres => {
+                let database = database
+                    .ok_or_else(|| eyre::eyre!("Postgres failure store requires `pg_url`"))?;
+                Arc::new(PgFailureStore::setup(database.clone()).await?)
+            }
+            FailureBackend::Custom(store) => store,
+        };
+        Ok(Throttle {
+            limits: Arc::new(Limits {
+                max_failures: self.max_failures,
+                window: self.window,
+                lockout: self.lockout,
+                delay_step: self.delay_step,
+                max_delay: self.max_delay,
+            }),
+            store,
+        })
+    }
+}
+
+struct Limits {
+    max_failures: i32,
+    window: Duration,
+    lockout: Duration,
+    delay_step: Duration,
+    max_delay: Duration,
+}
+
+/// Handle to the configured [`FailureStore`], extracted from the request to
+/// protect custom login handlers.
+#[derive(Clone)]
+pub struct Throttle {
+    limits: Arc<Limits>,
+    store: Arc<dyn FailureStore>,
+}
+
+impl OperationInput for Throttle {}
+impl<S: Sync> FromRequestParts<S> for Throttle {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        _: &S,
+    ) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Throttle>()
+            .cloned()
+            .ok_or_else(|| ApiError {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                title: "Throttling Not Configured".to_string(),
+                ..Default::default()
+            })
+    }
+}
+
+impl Throttle {
+    /// Reject locked out keys, and delay attempts of keys with recent
+    /// failures. To be called before verifying credentials.
+    pub async fn check(&self, keys: &[FailureKey]) -> Result<(), ApiError> {
+        let delay = self.ensure_not_locked(keys).await?;
+        if let Ok(delay) = delay.to_std() {
+            tokio::time::sleep(delay).await;
+        }
+        Ok(())
+    }
+
+    /// Count a failed attempt against every key, locking out those over the
+    /// limit
+    pub async fn failure(&self, keys: &[FailureKey]) -> Result<(), ApiError> {
+        let now = Utc::now();
+        for key in keys {
+            let key = key.to_string();
+            let entry = self
+                .store
+                .record_failure(&key, now, now - self.limits.window)
+                .await?;
+            if entry.failures >= self.limits.max_failures {
+                tracing::warn!(
+                    key,
+                    failures = entry.failures,
+                    "Locking out after repeated authentication failures"
+                );
+                self.store.lock(&key, now + self.limits.lockout).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget the failures of the accounts in `keys`. Failures of IPs are kept
+    /// so a valid account doesn't unlock guessing at others.
+    pub async fn success(&self, keys: &[FailureKey]) -> Result<(), ApiError> {
+        for key in keys {
+            if let FailureKey::Account(_) = key {
+                self.store.clear(&key.to_string()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The delay owed by the keys' recent failures
+    pub(crate) async fn ensure_not_locked(
+        &self,
+        keys: &[FailureKey],
+    ) -> Result<Duration, ApiError> {
+        let now = Utc::now();
+        let mut delay = Duration::zero();
+        for key in keys {
+            let Some(entry) = self.store.get(&key.to_string()).await? else {
+                continue;
+            };
+            if let Some(until) = entry.locked_until.filter(|until| *until > now) {
+                Err(locked_out(until - now))?;
+            }
+            if entry.last_failure_at > now - self.limits.window {
+                delay = delay.max(self.limits.delay_step * entry.failures);
+            }
+        }
+        Ok(delay.min(self.limits.max_delay))
+    }
+
+    /// Periodically prune stale entries for as long as the server runs
+    pub(crate) fn spawn_pruning(&self) {
+        let store = self.store.clone();
+        let window = self.limits.window;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+            loop {
+                interval.tick().await;
+                let now = Utc::now();
+                if let Err(err) = store.prune(now - window, now).await {
+                    tracing::error!("Error pruning authentication failures: {:?}", err);
+                }
+            }
+        });
+    }
+}
+
+/// Rejection of locked out clients and accounts
+pub(crate) const TOO_MANY_FAILED_ATTEMPTS: (u16, &str) = (429, "Too Many Failed Attempts");
+
+fn locked_out(retry_after: Duration) -> ApiError {
+    // Round up so clients retrying on time find the lockout over
+    let seconds = (retry_after.num_milliseconds() + 999) / 1000;
+    documented_error(TOO_MANY_FAILED_ATTEMPTS).with_header(RETRY_AFTER, HeaderValue::from(seconds))
+}
+
+/// Reverse proxies in front of the server, whose `X-Forwarded-For` and
+/// `X-Real-IP` are believed
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TrustedProxies(pub(crate) Arc<[IpAddr]>);
+
+/// Address of the client: the peer of the connection, unless it's one of the
+/// [`TrustedProxies`]. Then the last hop of `X-Forwarded-For` that isn't a
+/// trusted proxy, or `X-Real-IP`.
+pub(crate) fn client_ip(parts: &http::request::Parts) -> Option<IpAddr> {
+    let peer = parts
+        .extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())?;
+    let trusted = |ip: &IpAddr| {
+        parts
+            .extensions
+            .get::<TrustedProxies>()
+            .is_some_and(|TrustedProxies(proxies)| proxies.contains(ip))
+    };
+    if !trusted(&peer) {
+        return Some(peer);
+    }
+    forwarded_for(&parts.headers, trusted)
+        .or_else(|| {
+            parts
+                .headers
+                .get("x-real-ip")
+                .and_then(|h| h.to_str().ok())
+                .and_then(|ip| ip.trim().parse().ok())
+        })
+        .or(Some(peer))
+}
+
+/// Walk `X-Forwarded-For` back from the proxy closest to the server, up to the
+/// first hop that isn't trusted. Hops before it could be made up by anyone.
+fn forwarded_for(headers: &HeaderMap, trusted: impl Fn(&IpAddr) -> bool) -> Option<IpAddr> {
+    let hops: Vec<_> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .flat_map(|h| h.to_str().unwrap_or_default().split(','))
+        .map(|hop| hop.trim().parse::<IpAddr>())
+        .collect();
+    let mut client = None;
+    for hop in hops.into_iter().rev() {
+        let Ok(hop) = hop else {
+            break;
+        };
+        client = Some(hop);
+        if !trusted(&hop) {
+            break;
+        }
+    }
+    client
+}
+
+/// Address of the client, see [`Throttle`] and `Config::trusted_proxies`.
+/// Extract `Option<ClientIp>` where the address isn't required.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+impl OperationInput for ClientIp {}
+impl<S: Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        _: &S,
+    ) -> Result<Self, Self::Rejection> {
+        client_ip(parts).map(ClientIp).ok_or_else(|| ApiError {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            title: "Client Address Unknown".to_string(),
+            ..Default::default()
+        })
+    }
+}
+
+impl<S: Sync> OptionalFromRequestParts<S> for ClientIp {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        _: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        Ok(client_ip(parts).map(ClientIp))
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryFailureStore {
+    entries: RwLock<HashMap<String, FailureEntry>>,
+}
+
+#[async_trait]
+impl FailureStore for InMemoryFailureStore {
+    async fn get(&self, key: &str) -> Result<Option<FailureEntry>, ApiError> {
+        Ok(self.entries.read().unwrap().get(key).cloned())
+    }
+
+    async fn record_failure(
+        &self,
+        key: &str,
+        now: DateTime<Utc>,
+        window_start: DateTime<Utc>,
+    ) -> Result<FailureEntry, ApiError> {
+        let mut entries = self.entries.write().unwrap();
+        let entry = entries.entry(key.to_string()).or_insert(FailureEntry {
+            failures: 0,
+            last_failure_at: now,
+            locked_until: None,
+        });
+        if entry.last_failure_at < window_start {
+            entry.failures = 0;
+        }
+        entry.failures += 1;
+        entry.last_failure_at = now;
+        Ok(entry.clone())
+    }
+
+    async fn lock(&self, key: &str, until: DateTime<Utc>) -> Result<(), ApiError> {
+        if let Some(entry) = self.entries.write().unwrap().get_mut(key) {
+            entry.locked_until = Some(until);
+        }
+        Ok(())
+    }
+
+    async fn clear(&self, key: &str) -> Result<(), ApiError> {
+        self.entries.write().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn prune(&self, window_start: DateTime<Utc>, now: DateTime<Utc>) -> Result<(), ApiError> {
+        self.entries.write().unwrap().retain(|_, entry| {
+            entry.last_failure_at >= window_start || entry.locked_until.is_some_and(|u| u > now)
+        });
+        Ok(())
+    }
+}
+
+diesel::table! {
+    axum_api_auth_failures (key) {
+        key -> Text,
+        failures -> Integer,
+        last_failure_at -> Timestamptz,
+        locked_until -> Nullable<Timestamptz>,
+    }
+}
+
+pub struct PgFailureStore {
+    database: Database,
+}
+
+impl PgFailureStore {
+    /// Create the failure table if missing
+    pub async fn setup(database: Database) -> Result<Self, diesel::result::Error> {
+        diesel::sql_query(
+            "CREATE TABLE IF NOT EXISTS axum_api_auth_failures (
+                key TEXT PRIMARY KEY,
+                failures INTEGER NOT NULL,
+                last_failure_at TIMESTAMPTZ NOT NULL,
+                locked_until TIMESTAMPTZ
+            )",
+        )
+        .execute(&mut database.conn())
+        .await?;
+        Ok(Self { database })
+    }
+}
+
+#[async_trait]
+impl FailureStore for PgFailureStore {
+    async fn get(&self, key: &str) -> Result<Option<FailureEntry>, ApiError> {
+        use axum_api_auth_failures::dsl;
+        let entry = dsl::axum_api_auth_failures
+            .filter(dsl::key.eq(key))
+            .select(FailureEntry::as_select())
+            .load(&mut self.database.conn())
+            .await?
+            .pop();
+        Ok(entry)
+    }
+
+    async fn record_failure(
+        &self,
+        key: &str,
+        now: DateTime<Utc>,
+        window_start: DateTime<Utc>,
+    ) -> Result<FailureEntry, ApiError> {
+        // A single statement, so concurrent failures all get counted
+        let entry = diesel::sql_query(
+            "INSERT INTO axum_api_auth_failures (key, failures, last_failure_at)
+            VALUES ($1, 1, $2)
+            ON CONFLICT (key) DO UPDATE SET
+                failures = CASE
+                    WHEN axum_api_auth_failures.last_failure_at < $3 THEN 1
+                    ELSE axum_api_auth_failures.failures + 1
+                END,
+                last_failure_at = $2
+            RETURNING failures, last_failure_at, locked_until",
+        )
+        .bind::<Text, _>(key)
+        .bind::<Timestamptz, _>(now)
+        .bind::<Timestamptz, _>(window_start)
+        .get_result::<FailureEntry>(&mut self.database.conn())
+        .await?;
+        Ok(entry)
+    }
+
+    async fn lock(&self, key: &str, until: DateTime<Utc>) -> Result<(), ApiError> {
+        use axum_api_auth_failures::dsl;
+        diesel::update(dsl::axum_api_auth_failures.filter(dsl::key.eq(key)))
+            .set(dsl::locked_until.eq(until))
+            .execute(&mut self.database.conn())
+            .await?;
+        Ok(())
+    }
+
+    async fn clear(&self, key: &str) -> Result<(), ApiError> {
+        use axum_api_auth_failures::dsl;
+        diesel::delete(dsl::axum_api_auth_failures.filter(dsl::key.eq(key)))
+            .execute(&mut self.database.conn())
+            .await?;
+        Ok(())
+    }
+
+    async fn prune(&self, window_start: DateTime<Utc>, now: DateTime<Utc>) -> Result<(), ApiError> {
+        use axum_api_auth_failures::dsl;
+        diesel::delete(
+            dsl::axum_api_auth_failures.filter(
+                dsl::last_failure_at
+                    .lt(window_start)
+                    .and(dsl::locked_until.is_null().or(dsl::locked_until.le(now))),
+            ),
+        )
+        .execute(&mut self.database.conn())
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle(store: Arc<InMemoryFailureStore>) -> Throttle {
+        Throttle {
+            limits: Arc::new(Limits {
+                max_failures: 3,
+                window: Duration::minutes(15),
+                lockout: Duration::minutes(5),
+                delay_step: Duration::milliseconds(250),
+                max_delay: Duration::milliseconds(500),
+            }),
+            store,
+        }
+    }
+
+    fn account() -> [FailureKey; 1] {
+        [FailureKey::Account("alice".to_string())]
+    }
+
+    #[tokio::test]
+    async fn failures_delay_then_lock_out() {
+        let throttle = throttle(Arc::default());
+        throttle.failure(&account()).await.unwrap();
+        let delay = throttle.ensure_not_locked(&account()).await.unwrap();
+        assert_eq!(delay, Duration::milliseconds(250));
+        throttle.failure(&account()).await.unwrap();
+        let delay = throttle.ensure_not_locked(&account()).await.unwrap();
+        assert_eq!(delay, Duration::milliseconds(500));
+        throttle.failure(&account()).await.unwrap();
+        let err = throttle.ensure_not_locked(&account()).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(err.headers[RETRY_AFTER], "300");
+    }
+
+    #[tokio::test]
+    async fn failures_outside_the_window_start_over() {
+        let store = Arc::new(InMemoryFailureStore::default());
+        let throttle = throttle(store.clone());
+        let key = account()[0].to_string();
+        let long_ago = Utc::now() - Duration::hours(1);
+        for _ in 0..2 {
+            store
+                .record_failure(&key, long_ago, long_ago - Duration::minutes(15))
+                .await
+                .unwrap();
+        }
+        throttle.failure(&account()).await.unwrap();
+        let entry = store.get(&key).await.unwrap().unwrap();
+        assert_eq!(entry.failures, 1);
+        assert_eq!(entry.locked_until, None);
+    }
+
+    #[tokio::test]
+    async fn lockout_ends() {
+        let store = Arc::new(InMemoryFailureStore::default());
+        let throttle = throttle(store.clone());
+        let key = account()[0].to_string();
+        throttle.failure(&account()).await.unwrap();
+        store
+            .lock(&key, Utc::now() - Duration::seconds(1))
+            .await
+            .unwrap();
+        assert!(throttle.ensure_not_locked(&account()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn success_forgets_accounts_only() {
+        let throttle = throttle(Arc::default());
+        let keys = FailureKey::attempt(Some(ClientIp([10, 0, 0, 1].into())), "alice");
+        throttle.failure(&keys).await.unwrap();
+        throttle.success(&keys).await.unwrap();
+        assert!(
+            throttle
+                .store
+                .get(&keys[0].to_string())
+                .await
+                .unwrap()
+                .is_some()
+        );
+        assert!(
+            throttle
+                .store
+                .get(&keys[1].to_string())
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn prune_keeps_recent_and_locked_entries() {
+        let store = InMemoryFailureStore::default();
+        let now = Utc::now();
+        let window_start = now - Duration::minutes(15);
+        let long_ago = now - Duration::hours(1);
+        store
+            .record_failure("recent", now, window_start)
+            .await
+            .unwrap();
+        store
+            .record_failure("stale", long_ago, window_start)
+            .await
+            .unwrap();
+        store
+            .record_failure("locked", long_ago, window_start)
+            .await
+            .unwrap();
+        store
+            .lock("locked", now + Duration::minutes(1))
+            .await
+            .unwrap();
+        store.prune(window_start, now).await.unwrap();
+        assert!(store.get("recent").await.unwrap().is_some());
+        assert!(store.get("stale").await.unwrap().is_none());
+        assert!(store.get("locked").await.unwrap().is_some());
+    }
+
+    fn request_parts(peer: [u8; 4], headers: &[(&str, &str)]) -> http::request::Parts {
+        let mut req = http::Request::builder()
+            .extension(ConnectInfo(SocketAddr::from((peer, 443))))
+            .extension(TrustedProxies(Arc::new([IpAddr::from([10, 0, 0, 1])])));
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        req.body(()).unwrap().into_parts().0
+    }
+
+    #[test]
+    fn forwarded_headers_of_untrusted_peers_are_ignored() {
+        let parts = request_parts(
+            [203, 0, 113, 9],
+            &[
+                ("x-forwarded-for", "198.51.100.7"),
+                ("x-real-ip", "198.51.100.7"),
+            ],
+        );
+        assert_eq!(client_ip(&parts), Some([203, 0, 113, 9].into()));
+    }
+
+    #[test]
+    fn trusted_proxies_tell_the_last_untrusted_hop() {
+        let parts = request_parts(
+            [10, 0, 0, 1],
+            &[("x-forwarded-for", "192.0.2.1, 198.51.100.7, 10.0.0.1")],
+        );
+        assert_eq!(client_ip(&parts), Some([198, 51, 100, 7].into()));
+        let parts = request_parts([10, 0, 0, 1], &[("x-real-ip", "198.51.100.7")]);
+        assert_eq!(client_ip(&parts), Some([198, 51, 100, 7].into()));
+    }
+
+    #[test]
+    fn no_address_without_connect_info() {
+        let parts = http::Request::new(()).into_parts().0;
+        assert_eq!(client_ip(&parts), None);
+    }
+}
@@ -1,4 +1,5 @@
 use {
+    crate::tenant::{Tenant, current_tenant},
     axum_tracing_opentelemetry::tracing_opentelemetry_instrumentation_sdk,
     diesel::connection::Instrumentation,
     diesel_async::{AsyncConnectionCore, methods, return_futures},
@@ -104,14 +105,19 @@ pub trait RunQueryDsl<Conn>: Sized {
 }
 
 fn make_otel_span() -> tracing::Span {
-    tracing_opentelemetry_instrumentation_sdk::otel_trace_span!(
+    let span = tracing_opentelemetry_instrumentation_sdk::otel_trace_span!(
         "Diesel SQL",
         "error.type" = Empty,
+        "tenant.id" = Empty,
         db.system = "postgresql",
         otel.kind = "CLIENT",
         db.query.text = Empty,
         otel.status_code = Empty
-    )
+    );
+    if let Some(Tenant(tenant)) = current_tenant() {
+        span.record("tenant.id", tenant);
+    }
+    span
 }
 
 impl<T, Conn> RunQueryDsl<Conn> for T {}
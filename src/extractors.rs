@@ -1,39 +1,66 @@
+mod async_validate;
+mod body;
 mod jwt;
 mod multipart;
 mod path;
 mod typed_multipart;
+mod valid_form;
 mod valid_json;
 mod valid_query;
 
+pub(crate) use jwt::{JWT_SECURITY_SCHEME, TOKEN_ERRORS, request_token, require_jwt_scopes};
 use {
-    aide::OperationInput, axum::extract::FromRequestParts, diesel_async::AsyncPgConnection,
+    crate::api_error::ApiError,
+    aide::OperationInput,
+    axum::{extract::FromRequestParts, http::StatusCode},
+    diesel_async::AsyncPgConnection,
     std::sync::Arc,
 };
 pub use {
-    jwt::{Claims, Jwt, jwt_open_api},
+    async_validate::AsyncValidate,
+    body::{AcceptsBody, Body},
+    jwt::{Actor, Claims, ForbidImpersonation, Jwt, OptionalClaims, Principal, jwt_open_api},
     multipart::Multipart,
-    path::Path,
-    typed_multipart::TypedMultipart,
-    valid_json::Json,
-    valid_query::Query,
+    path::{Path, ValidPath},
+    typed_multipart::{
+        FileField, FileRule, TypedMultipart, UploadedFile, ValidTypedMultipart, ValidateFiles,
+    },
+    valid_form::Form,
+    valid_json::{AsyncJson, Json},
+    valid_query::{AsyncQuery, Query},
 };
 
 #[derive(Clone)]
 pub struct Database(Arc<AsyncPgConnection>);
 impl Database {
+    pub(crate) fn new(conn: Arc<AsyncPgConnection>) -> Self {
+        Self(conn)
+    }
+
     pub fn conn(&self) -> &AsyncPgConnection {
         self.0.as_ref()
     }
+
+    pub(crate) fn into_inner(self) -> Arc<AsyncPgConnection> {
+        self.0
+    }
 }
 impl OperationInput for Database {}
 impl<S: Sync> FromRequestParts<S> for Database {
-    type Rejection = ();
+    type Rejection = ApiError;
 
     async fn from_request_parts(
         parts: &mut axum::http::request::Parts,
         _state: &S,
     ) -> Result<Self, Self::Rejection> {
-        let db = parts.extensions.get::<Arc<AsyncPgConnection>>().ok_or(())?;
+        let db = parts
+            .extensions
+            .get::<Arc<AsyncPgConnection>>()
+            .ok_or_else(|| ApiError {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                title: "Database Not Configured".to_string(),
+                ..Default::default()
+            })?;
         Ok(Database(db.clone()))
     }
 }
@@ -0,0 +1,78 @@
+use {
+    crate::api_error::ApiError,
+    axum::extract::FromRequestParts,
+    std::collections::hash_map::Entry,
+    validator::{Validate, ValidationErrors, ValidationErrorsKind},
+};
+
+/// Validation needing the request, e.g. an email not registered yet, run by
+/// [`AsyncJson`](super::AsyncJson) and [`AsyncQuery`](super::AsyncQuery)
+/// once [`Validate`] passed.
+///
+/// ```ignore
+/// impl<S: Send + Sync> AsyncValidate<S> for NewUser {
+///     type Context = Database;
+///
+///     async fn validate_async(&self, database: &Database) -> Result<(), ValidationErrors> {
+///         let mut errors = ValidationErrors::new();
+///         if email_taken(database, &self.email).await {
+///             errors.add("email", ValidationError::new("taken"));
+///         }
+///         if errors.is_empty() { Ok(()) } else { Err(errors) }
+///     }
+/// }
+/// ```
+pub trait AsyncValidate<S>: Send + Sync {
+    /// Extracted from the request before validating, e.g. `Database` or
+    /// `Claims<T>`, or a struct deriving `FromRequestParts` for several
+    type Context: FromRequestParts<S, Rejection: Into<ApiError>> + Send + Sync;
+
+    fn validate_async(
+        &self,
+        context: &Self::Context,
+    ) -> impl Future<Output = Result<(), ValidationErrors>> + Send;
+}
+
+/// Validate `data` with [`Validate`], then with [`AsyncValidate`] only if it
+/// passed, sparing the lookups of the request for input already rejected
+pub(crate) async fn validate_with_context<T, S>(
+    data: &T,
+    context: &T::Context,
+) -> Result<(), ValidationErrors>
+where
+    T: Validate + AsyncValidate<S>,
+{
+    data.validate()?;
+    data.validate_async(context).await
+}
+
+/// Add the failures of `more` to `errors`
+pub(crate) fn merge(errors: &mut ValidationErrors, more: ValidationErrors) {
+    for (field, kind) in more.0 {
+        match errors.0.entry(field) {
+            Entry::Vacant(entry) => {
+                entry.insert(kind);
+            }
+            Entry::Occupied(mut entry) => match (entry.get_mut(), kind) {
+                (ValidationErrorsKind::Field(failures), ValidationErrorsKind::Field(more)) => {
+                    failures.extend(more)
+                }
+                (ValidationErrorsKind::Struct(nested), ValidationErrorsKind::Struct(more)) => {
+                    merge(nested, *more)
+                }
+                (ValidationErrorsKind::List(items), ValidationErrorsKind::List(more)) => {
+                    for (index, more) in more {
+                        match items.get_mut(&index) {
+                            Some(nested) => merge(nested, *more),
+                            None => {
+                                items.insert(index, more);
+                            }
+                        }
+                    }
+                }
+                // A field can't be both a value and a struct, keep the first
+                _ => {}
+            },
+        }
+    }
+}
@@ -0,0 +1,429 @@
+use {
+    super::valid_json::json_from_slice,
+    crate::api_error::{
+        ApiError, FieldError, Internal, IntoApiError, error_responses, from_form, from_value,
+        input_error_responses, request_accept,
+    },
+    aide::{
+        OperationInput, OperationOutput,
+        openapi::{MediaType, RequestBody, Response, SchemaObject, StatusCode},
+        operation::set_body,
+    },
+    axum::{
+        extract::{FromRequest, FromRequestParts},
+        http::{
+            self, HeaderMap, HeaderValue,
+            header::{ACCEPT, CONTENT_TYPE, VARY},
+        },
+        response::IntoResponse,
+    },
+    derive_more::{AsMut, AsRef, Deref, DerefMut, From},
+    indexmap::IndexMap,
+    schemars::JsonSchema,
+    serde::{Serialize, de::DeserializeOwned},
+    validator::Validate,
+};
+
+/// Body read as JSON, MessagePack, CBOR or form following `Content-Type`, and
+/// written as JSON, MessagePack or CBOR following `Accept`. JSON is answered
+/// when any format is accepted.
+///
+/// Requests accepting none of the formats are rejected with 406 before the
+/// handler runs. Handlers answering with a `Body` without reading one take
+/// [`AcceptsBody`] for that.
+#[derive(Debug, Clone, Copy, Default, Deref, DerefMut, From, AsRef, AsMut, PartialEq)]
+pub struct Body<T>(pub T);
+
+/// Rejects requests accepting none of the formats of [`Body`] with 406, e.g.
+/// `async fn user(_: AcceptsBody, ..) -> Body<User>`
+#[derive(Debug, Clone, Copy)]
+pub struct AcceptsBody;
+
+/// Formats of [`Body`], by preference when several are accepted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Json,
+    MessagePack,
+    Cbor,
+    Form,
+}
+
+impl Format {
+    const ALL: [Self; 4] = [Self::Json, Self::MessagePack, Self::Cbor, Self::Form];
+    /// Formats answered, forms can't hold every response
+    const RESPONSES: [Self; 3] = [Self::Json, Self::MessagePack, Self::Cbor];
+
+    fn media_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::MessagePack => "application/msgpack",
+            Self::Cbor => "application/cbor",
+            Self::Form => "application/x-www-form-urlencoded",
+        }
+    }
+
+    /// Format of a media type without parameters, e.g. `application/cbor`
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        let (kind, subtype) = media_type.trim().split_once('/')?;
+        if !kind.eq_ignore_ascii_case("application") {
+            return None;
+        }
+        let subtype = subtype.to_ascii_lowercase();
+        match subtype.as_str() {
+            "json" => Some(Self::Json),
+            "msgpack" | "x-msgpack" | "vnd.msgpack" => Some(Self::MessagePack),
+            "cbor" => Some(Self::Cbor),
+            "x-www-form-urlencoded" => Some(Self::Form),
+            _ if subtype.ends_with("+json") => Some(Self::Json),
+            _ if subtype.ends_with("+cbor") => Some(Self::Cbor),
+            _ => None,
+        }
+    }
+
+    fn of_request(headers: &HeaderMap) -> Result<Self, ApiError> {
+        headers
+            .get(CONTENT_TYPE)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|content_type| Self::from_media_type(content_type.split(';').next()?))
+            .ok_or_else(|| unsupported_media_type(&Self::ALL))
+    }
+
+    /// Most preferred response format of `accept`, JSON without the header
+    fn negotiate(accept: Option<&str>) -> Option<Self> {
+        let Some(accept) = accept.filter(|accept| !accept.trim().is_empty()) else {
+            return Some(Self::Json);
+        };
+        let mut ranges: Vec<(f32, &str)> = accept
+            .split(',')
+            .filter_map(|item| {
+                let mut item = item.split(';');
+                let range = item.next()?.trim();
+                let quality = item
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.trim().parse().ok())?;
+                (quality > 0.0).then_some((quality, range))
+            })
+            .collect();
+        // Stable, ranges of the same quality keep the client's order
+        ranges.sort_by(|a, b| b.0.total_cmp(&a.0));
+        ranges.into_iter().find_map(|(_, range)| match range {
+            "*/*" | "application/*" => Some(Self::Json),
+            range => Self::from_media_type(range).filter(|format| Self::RESPONSES.contains(format)),
+        })
+    }
+
+    fn accepted(headers: &HeaderMap) -> Result<Self, ApiError> {
+        let accept = headers.get(ACCEPT).and_then(|h| h.to_str().ok());
+        Self::negotiate(accept).ok_or_else(not_acceptable)
+    }
+
+    fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, ApiError> {
+        // Decoded as a value first, so malformed input isn't taken for a
+        // field of the wrong type
+        let value = match self {
+            Self::Json => return json_from_slice(bytes),
+            Self::Form => return from_form(bytes).map_err(|error| self.rejected(error)),
+            Self::MessagePack => {
+                rmp_serde::from_slice(bytes).map_err(|e| self.malformed(&e.to_string()))?
+            }
+            Self::Cbor => ciborium::from_reader(bytes).map_err(|e| {
+                self.malformed(&match e {
+                    ciborium::de::Error::Io(e) => e.to_string(),
+                    ciborium::de::Error::Syntax(offset) => format!("invalid CBOR at byte {offset}"),
+                    ciborium::de::Error::Semantic(_, message) => message,
+                    ciborium::de::Error::RecursionLimitExceeded => {
+                        "recursion limit exceeded".to_string()
+                    }
+                })
+            })?,
+        };
+        from_value(value).map_err(|error| self.rejected(error))
+    }
+
+    fn rejected(self, error: FieldError) -> ApiError {
+        ApiError {
+            status: http::StatusCode::UNPROCESSABLE_ENTITY,
+            title: "Body Rejection".to_string(),
+            detail: Some(format!(
+                "Failed to deserialize the {} body into the target type: {}",
+                self.media_type(),
+                error.message.as_deref().unwrap_or_default()
+            )),
+            ..Default::default()
+        }
+        .with_field_errors(vec![error])
+    }
+
+    fn malformed(self, message: &str) -> ApiError {
+        ApiError {
+            status: http::StatusCode::BAD_REQUEST,
+            title: "Body Rejection".to_string(),
+            detail: Some(format!(
+                "Failed to parse the request body as {}: {message}",
+                self.media_type()
+            )),
+            ..Default::default()
+        }
+    }
+
+    fn encode<T: Serialize>(self, data: &T) -> Result<Vec<u8>, ApiError> {
+        match self {
+            Self::Json => serde_json::to_vec(data).map_err(|e| Internal(e).into_error_response()),
+            Self::MessagePack => {
+                rmp_serde::to_vec_named(data).map_err(|e| Internal(e).into_error_response())
+            }
+            Self::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(data, &mut bytes)
+                    .map_err(|e| Internal(e).into_error_response())?;
+                Ok(bytes)
+            }
+            Self::Form => unreachable!("forms aren't negotiated"),
+        }
+    }
+}
+
+fn unsupported_media_type(formats: &[Format]) -> ApiError {
+    ApiError {
+        status: http::StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        title: "Unsupported Media Type".to_string(),
+        detail: Some(format!("Expected a body of {}", media_types(formats))),
+        ..Default::default()
+    }
+}
+
+fn not_acceptable() -> ApiError {
+    ApiError {
+        status: http::StatusCode::NOT_ACCEPTABLE,
+        title: "Not Acceptable".to_string(),
+        detail: Some(format!(
+            "Can answer with {}",
+            media_types(&Format::RESPONSES)
+        )),
+        ..Default::default()
+    }
+    .with_header(VARY, HeaderValue::from_static("accept"))
+}
+
+fn media_types(formats: &[Format]) -> String {
+    formats
+        .iter()
+        .map(|format| format.media_type())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl<S, T> FromRequest<S> for Body<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: axum::extract::Request, state: &S) -> Result<Self, Self::Rejection> {
+        Format::accepted(req.headers())?;
+        let format = Format::of_request(req.headers())?;
+        let data: T = format.decode(&read_bytes(req, state).await?)?;
+        data.validate()?;
+        Ok(Body(data))
+    }
+}
+
+/// Deserialize an `application/x-www-form-urlencoded` body, as [`Body`] does
+pub(crate) async fn form_body<T, S>(req: axum::extract::Request, state: &S) -> Result<T, ApiError>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    if Format::of_request(req.headers()).ok() != Some(Format::Form) {
+        return Err(unsupported_media_type(&[Format::Form]));
+    }
+    Format::Form.decode(&read_bytes(req, state).await?)
+}
+
+async fn read_bytes<S: Send + Sync>(
+    req: axum::extract::Request,
+    state: &S,
+) -> Result<axum::body::Bytes, ApiError> {
+    axum::body::Bytes::from_request(req, state)
+        .await
+        .map_err(|rejection| ApiError {
+            status: rejection.status(),
+            title: "Body Rejection".to_string(),
+            detail: Some(rejection.body_text()),
+            ..Default::default()
+        })
+}
+
+impl<S: Sync> FromRequestParts<S> for AcceptsBody {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Format::accepted(&parts.headers)?;
+        Ok(Self)
+    }
+}
+
+impl<T: Serialize> IntoResponse for Body<T> {
+    fn into_response(self) -> axum::response::Response {
+        // Unacceptable requests are rejected before the handler runs, if it
+        // didn't check, JSON is answered as if `Accept` was ignored
+        let format = Format::negotiate(request_accept().as_deref()).unwrap_or(Format::Json);
+        match format.encode(&self.0) {
+            Ok(bytes) => (
+                [(CONTENT_TYPE, format.media_type()), (VARY, "accept")],
+                bytes,
+            )
+                .into_response(),
+            Err(err) => err.into_response(),
+        }
+    }
+}
+
+/// `schema` under each media type of `formats`
+fn content(json_schema: schemars::Schema, formats: &[Format]) -> IndexMap<String, MediaType> {
+    formats
+        .iter()
+        .map(|format| {
+            let media = MediaType {
+                schema: Some(SchemaObject {
+                    json_schema: json_schema.clone(),
+                    external_docs: None,
+                    example: None,
+                }),
+                ..Default::default()
+            };
+            (format.media_type().to_string(), media)
+        })
+        .collect()
+}
+
+impl<T: JsonSchema> OperationInput for Body<T> {
+    fn operation_input(
+        ctx: &mut aide::generate::GenContext,
+        operation: &mut aide::openapi::Operation,
+    ) {
+        let json_schema = ctx.schema.subschema_for::<T>();
+        set_body(
+            ctx,
+            operation,
+            RequestBody {
+                description: None,
+                content: content(json_schema, &Format::ALL),
+                required: true,
+                extensions: IndexMap::default(),
+            },
+        );
+    }
+
+    fn inferred_early_responses(
+        ctx: &mut aide::generate::GenContext,
+        operation: &mut aide::openapi::Operation,
+    ) -> Vec<(Option<StatusCode>, aide::openapi::Response)> {
+        input_error_responses(
+            ctx,
+            operation,
+            &[
+                (400, "Axum Json Rejection"),
+                (400, "Body Rejection"),
+                (400, "Validation error"),
+                (406, "Not Acceptable"),
+                (415, "Unsupported Media Type"),
+                (422, "Axum Json Rejection"),
+                (422, "Body Rejection"),
+            ],
+        )
+    }
+}
+
+impl OperationInput for AcceptsBody {
+    fn inferred_early_responses(
+        ctx: &mut aide::generate::GenContext,
+        operation: &mut aide::openapi::Operation,
+    ) -> Vec<(Option<StatusCode>, aide::openapi::Response)> {
+        error_responses(ctx, operation, &[(406, "Not Acceptable")])
+    }
+}
+
+impl<T: JsonSchema> OperationOutput for Body<T> {
+    type Inner = T;
+
+    fn operation_response(
+        ctx: &mut aide::generate::GenContext,
+        _operation: &mut aide::openapi::Operation,
+    ) -> Option<Response> {
+        let json_schema = ctx.schema.subschema_for::<T>();
+        Some(Response {
+            content: content(json_schema, &Format::RESPONSES),
+            ..Default::default()
+        })
+    }
+
+    fn inferred_responses(
+        ctx: &mut aide::generate::GenContext,
+        operation: &mut aide::openapi::Operation,
+    ) -> Vec<(Option<StatusCode>, Response)> {
+        Self::operation_response(ctx, operation)
+            .map(|res| (Some(StatusCode::Code(200)), res))
+            .into_iter()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, serde::Deserialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Item {
+        id: u8,
+        name: String,
+    }
+
+    #[test]
+    fn forms_are_read_but_not_answered() {
+        let accept = "application/x-www-form-urlencoded, application/cbor;q=0.5";
+        assert_eq!(Format::negotiate(Some(accept)), Some(Format::Cbor));
+        assert_eq!(
+            Format::negotiate(Some("application/x-www-form-urlencoded")),
+            None
+        );
+        assert_eq!(Format::negotiate(None), Some(Format::Json));
+        assert_eq!(
+            Format::Form.decode::<Item>(b"id=3&name=a").unwrap(),
+            Item {
+                id: 3,
+                name: "a".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn failures_name_the_field_in_every_format() {
+        let item = Item {
+            id: 3,
+            name: "a".to_string(),
+        };
+        let wrong = serde_json::json!({ "id": "three", "name": "a" });
+        for format in Format::RESPONSES {
+            assert_eq!(
+                format
+                    .decode::<Item>(&format.encode(&item).unwrap())
+                    .unwrap(),
+                item
+            );
+            let err = format
+                .decode::<Item>(&format.encode(&wrong).unwrap())
+                .unwrap_err();
+            assert_eq!(err.status, http::StatusCode::UNPROCESSABLE_ENTITY);
+            let error = &err.extensions.unwrap()["errors"][0];
+            assert_eq!(error["pointer"], "/id");
+            assert_eq!(error["code"], "type");
+        }
+        let err = Format::Cbor.decode::<Item>(&[0xff]).unwrap_err();
+        assert_eq!(err.status, http::StatusCode::BAD_REQUEST);
+    }
+}
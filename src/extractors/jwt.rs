@@ -1,34 +1,85 @@
 use {
-    crate::{api_error::ApiError, extractors::Json},
+    crate::{
+        api_error::{ApiError, IntoApiError, add_responses, documented_error, error_responses},
+        auth::{
+            cookie::{CSRF_TOKEN_MISMATCH, JwtCookie},
+            revocation::{Revocations, TOKEN_REVOKED},
+            throttle::{FailureKey, TOO_MANY_FAILED_ATTEMPTS, Throttle, client_ip},
+        },
+    },
     aide::{
         OperationInput,
-        transform::{TransformOperation, TransformPathItem},
+        openapi::{Operation, Response, SecurityRequirement},
+        transform::TransformPathItem,
     },
     axum::{
-        extract::FromRequestParts,
+        extract::{FromRequestParts, OptionalFromRequestParts},
         http::{self, StatusCode},
     },
     chrono::{DateTime, Duration, Utc, serde::ts_seconds},
     derive_more::{Deref, DerefMut},
-    jsonwebtoken::{DecodingKey, EncodingKey, Header, TokenData, Validation, decode, encode},
+    jsonwebtoken::{
+        DecodingKey, EncodingKey, Header, TokenData, Validation, decode, encode, errors::ErrorKind,
+    },
     schemars::JsonSchema,
-    serde::{Deserialize, Serialize, de::DeserializeOwned},
+    serde::{Deserialize, Deserializer, Serialize, de::DeserializeOwned},
     std::sync::Arc,
+    tracing_opentelemetry::OpenTelemetrySpanExt,
+    uuid::Uuid,
 };
 
+/// Name of the JWT security scheme in the generated OpenAPI
+pub(crate) const JWT_SECURITY_SCHEME: &str = "Json Web Token";
+
 #[derive(Serialize, Deserialize, Deref, DerefMut)]
 pub struct Claims<T> {
     #[serde(with = "ts_seconds")]
     pub exp: DateTime<Utc>,
     #[serde(with = "ts_seconds")]
     pub iat: DateTime<Utc>,
+    /// Unique token id, used to revoke a single token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    /// Set on tokens minted by [`Jwt::encode_impersonation`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
     #[deref]
     #[deref_mut]
     #[serde(flatten)]
     pub inner: T,
 }
 
-impl<T> OperationInput for Claims<T> {}
+/// The party acting on behalf of the token's subject (RFC 8693 `act` claim)
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Actor {
+    pub sub: String,
+}
+
+const MISSING_TOKEN: (u16, &str) = (401, "Missing Token");
+
+const IMPERSONATION_NOT_ALLOWED: (u16, &str) = (403, "Impersonation Not Allowed");
+
+/// Rejections of [`Claims`], documented on every operation using it. Those
+/// after the first are also rejections of [`OptionalClaims`].
+pub(crate) const TOKEN_ERRORS: &[(u16, &str)] = &[
+    MISSING_TOKEN,
+    TokenRejection::Invalid.error(),
+    TokenRejection::NotAccepted.error(),
+    TokenRejection::Expired.error(),
+    TOKEN_REVOKED,
+    CSRF_TOKEN_MISMATCH,
+    IMPERSONATION_NOT_ALLOWED,
+    TOO_MANY_FAILED_ATTEMPTS,
+];
+
+impl<T> OperationInput for Claims<T> {
+    fn inferred_early_responses(
+        ctx: &mut aide::generate::GenContext,
+        operation: &mut Operation,
+    ) -> Vec<(Option<aide::openapi::StatusCode>, Response)> {
+        error_responses(ctx, operation, TOKEN_ERRORS)
+    }
+}
 impl<S: Sync, T: DeserializeOwned> FromRequestParts<S> for Claims<T> {
     type Rejection = ApiError;
 
@@ -36,23 +87,198 @@ impl<S: Sync, T: DeserializeOwned> FromRequestParts<S> for Claims<T> {
         parts: &mut axum::http::request::Parts,
         state: &S,
     ) -> Result<Self, Self::Rejection> {
+        <Self as OptionalFromRequestParts<S>>::from_request_parts(parts, state)
+            .await?
+            .ok_or_else(|| documented_error(MISSING_TOKEN))
+    }
+}
+
+/// Yields `None` when no token is sent, malformed, expired or revoked tokens
+/// are still rejected
+impl<S: Sync, T: DeserializeOwned> OptionalFromRequestParts<S> for Claims<T> {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
         let jwt = Jwt::from_request_parts(parts, state).await?;
-        let token = parts
-            .headers
-            .get(http::header::AUTHORIZATION)
-            .and_then(|h| h.to_str().ok())
-            .and_then(|header| header.strip_prefix("Bearer "))
-            .map(|t| t.trim())
-            .filter(|t| !t.is_empty())
-            .ok_or_else(|| ApiError {
-                status: StatusCode::UNAUTHORIZED,
-                title: "Missing Token".to_string(),
-                ..Default::default()
-            })?;
-        jwt.decode(token)
+        let Some(token) = request_token(parts)? else {
+            return Ok(None);
+        };
+        let (claims, sub) = match jwt.decode_registered(&token, None) {
+            Ok(decoded) => decoded,
+            // Tokens we didn't sign count as failures of the client's address,
+            // unlike the expired tokens of legitimate clients
+            Err(TokenRejection::Invalid) => {
+                let throttle = parts.extensions.get::<Throttle>().cloned();
+                if let Some((throttle, ip)) = throttle.zip(client_ip(parts)) {
+                    let keys = [FailureKey::Ip(ip)];
+                    throttle.ensure_not_locked(&keys).await?;
+                    throttle.failure(&keys).await?;
+                }
+                Err(TokenRejection::Invalid)?
+            }
+            Err(rejection) => Err(rejection)?,
+        };
+        if claims.act.is_some() && parts.extensions.get::<ForbidImpersonation>().is_some() {
+            Err(documented_error(IMPERSONATION_NOT_ALLOWED))?;
+        }
+        record_identity(sub.as_deref(), claims.act.as_ref());
+        if let Some(revocations) = parts.extensions.get::<Revocations>() {
+            revocations
+                .ensure_not_revoked(&claims, sub.as_deref())
+                .await?;
+        }
+        Ok(Some(claims))
     }
 }
 
+/// Record the subject and impersonating actor of the request in its span, and
+/// audit impersonated requests
+fn record_identity(sub: Option<&str>, act: Option<&Actor>) {
+    let span = tracing::Span::current();
+    if let Some(sub) = sub {
+        span.set_attribute("enduser.id", sub.to_string());
+    }
+    if let Some(act) = act {
+        span.set_attribute("enduser.actor", act.sub.clone());
+        tracing::info!(
+            target: "audit",
+            sub = sub.unwrap_or_default(),
+            actor = %act.sub,
+            "Impersonated request"
+        );
+    }
+}
+
+/// Marks routes impersonated tokens are rejected on with 403, e.g.
+/// `.route_layer(Extension(ForbidImpersonation))`
+#[derive(Debug, Clone, Copy)]
+pub struct ForbidImpersonation;
+
+/// Identity of the caller, with the actor if the token is an impersonation
+pub struct Principal {
+    /// The user the request is made as
+    pub subject: String,
+    pub actor: Option<Actor>,
+}
+
+impl Principal {
+    pub fn is_impersonated(&self) -> bool {
+        self.actor.is_some()
+    }
+}
+
+#[derive(Deserialize)]
+struct Subject {
+    #[serde(default, deserialize_with = "lenient_sub")]
+    sub: Option<String>,
+}
+
+/// `sub` as a string, also when an issuer mints it as a number, `None` when
+/// it's anything else
+fn lenient_sub<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Ok(
+        match Option::<serde_json::Value>::deserialize(deserializer)? {
+            Some(serde_json::Value::String(sub)) => Some(sub),
+            Some(serde_json::Value::Number(sub)) => Some(sub.to_string()),
+            _ => None,
+        },
+    )
+}
+
+impl OperationInput for Principal {}
+impl<S: Sync> FromRequestParts<S> for Principal {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let claims = Claims::<Subject>::from_request_parts(parts, state).await?;
+        Ok(Self {
+            subject: claims.inner.sub.ok_or(TokenRejection::Invalid)?,
+            actor: claims.act,
+        })
+    }
+}
+
+/// Claims of callers that may stay anonymous, documented in OpenAPI with an
+/// optional security requirement.
+#[derive(Deref, DerefMut)]
+pub struct OptionalClaims<T>(pub Option<Claims<T>>);
+
+impl<S: Sync, T: DeserializeOwned> FromRequestParts<S> for OptionalClaims<T> {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let claims =
+            <Claims<T> as OptionalFromRequestParts<S>>::from_request_parts(parts, state).await?;
+        Ok(Self(claims))
+    }
+}
+
+impl<T> OperationInput for OptionalClaims<T> {
+    fn operation_input(
+        _ctx: &mut aide::generate::GenContext,
+        operation: &mut aide::openapi::Operation,
+    ) {
+        require_jwt_scopes(operation, &[]);
+        if !operation.security.iter().any(|r| r.is_empty()) {
+            operation.security.push(SecurityRequirement::default());
+        }
+    }
+
+    fn inferred_early_responses(
+        ctx: &mut aide::generate::GenContext,
+        operation: &mut Operation,
+    ) -> Vec<(Option<aide::openapi::StatusCode>, Response)> {
+        // Anonymous callers aren't rejected
+        error_responses(ctx, operation, &TOKEN_ERRORS[1..])
+    }
+}
+
+/// The token of the request, from the `Authorization` header or else from
+/// the [`JwtCookie`] if configured
+pub(crate) fn request_token(parts: &http::request::Parts) -> Result<Option<String>, ApiError> {
+    let bearer = parts
+        .headers
+        .get(http::header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .map(|t| t.trim())
+        .filter(|t| !t.is_empty());
+    match (bearer, parts.extensions.get::<JwtCookie>()) {
+        (Some(token), _) => Ok(Some(token.to_string())),
+        (None, Some(cookie)) => cookie.token(parts),
+        (None, None) => Ok(None),
+    }
+}
+
+/// Registered claims read aside of `T`
+#[derive(Deserialize)]
+struct Registered {
+    #[serde(default, deserialize_with = "lenient_sub")]
+    sub: Option<String>,
+    /// Set on tokens only accepted by a dedicated endpoint, e.g. MFA
+    /// verification, which [`Claims`] rejects. Namespaced so it can't be
+    /// mistaken for a field of the payload.
+    #[serde(rename = "axum_api:purpose")]
+    purpose: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Purposed<'a, T> {
+    #[serde(rename = "axum_api:purpose")]
+    purpose: &'a str,
+    #[serde(flatten)]
+    inner: T,
+}
+
 #[derive(Clone)]
 pub struct Jwt {
     keys: Arc<JwtKey>,
@@ -77,18 +303,44 @@ impl<S: Sync> FromRequestParts<S> for Jwt {
 
 impl Jwt {
     pub fn encode<T: Serialize>(&self, data: T, expiration: Duration) -> Result<String, ApiError> {
+        self.encode_claims(data, None, expiration)
+    }
+
+    /// Encode a token for the subject in `data` on which `actor` acts, e.g.
+    /// support staff seeing the app as a customer. Whether `actor` may
+    /// impersonate that subject is up to the caller.
+    pub fn encode_impersonation<T: Serialize>(
+        &self,
+        data: T,
+        actor: &str,
+        expiration: Duration,
+    ) -> Result<String, ApiError> {
+        let actor = Actor {
+            sub: actor.to_string(),
+        };
+        self.encode_claims(data, Some(actor), expiration)
+    }
+
+    fn encode_claims<T: Serialize>(
+        &self,
+        data: T,
+        act: Option<Actor>,
+        expiration: Duration,
+    ) -> Result<String, ApiError> {
         let iat = Utc::now();
         let exp = iat.checked_add_signed(expiration).unwrap();
         let claims = Claims {
             iat,
             exp,
+            jti: Some(Uuid::new_v4().to_string()),
+            act,
             inner: data,
         };
         let jwt = encode(&Header::default(), &claims, &self.keys.enc).map_err(|e| ApiError {
             status: StatusCode::INTERNAL_SERVER_ERROR,
             title: "Token Creation Failure".to_string(),
             detail: Some(e.to_string()),
-            extensions: None,
+            ..Default::default()
         })?;
         Ok(jwt)
     }
@@ -100,22 +352,57 @@ impl Jwt {
     }
 
     pub fn decode<T: DeserializeOwned>(&self, token: &str) -> Result<Claims<T>, ApiError> {
-        let token_data = self.decode_raw::<Claims<T>>(token).map_err(|err| {
-            tracing::error!("Error decoding token: {:?}", err);
-            ApiError {
-                status: StatusCode::UNAUTHORIZED,
-                title: "Invalid Token".to_string(),
-                ..Default::default()
+        Ok(self.decode_registered(token, None)?.0)
+    }
+
+    /// Encode a token only accepted by [`Jwt::decode_purpose`] with the same
+    /// `purpose`, never by [`Claims`]
+    pub fn encode_purpose<T: Serialize>(
+        &self,
+        data: T,
+        purpose: &str,
+        expiration: Duration,
+    ) -> Result<String, ApiError> {
+        self.encode(
+            Purposed {
+                purpose,
+                inner: data,
+            },
+            expiration,
+        )
+    }
+
+    pub fn decode_purpose<T: DeserializeOwned>(
+        &self,
+        token: &str,
+        purpose: &str,
+    ) -> Result<Claims<T>, ApiError> {
+        Ok(self.decode_registered(token, Some(purpose))?.0)
+    }
+
+    /// Decode a token of `purpose`, also yielding its `sub`
+    pub(crate) fn decode_registered<T: DeserializeOwned>(
+        &self,
+        token: &str,
+        purpose: Option<&str>,
+    ) -> Result<(Claims<T>, Option<String>), TokenRejection> {
+        let value = match self.decode_raw::<serde_json::Value>(token) {
+            Ok(data) => data.claims,
+            Err(err) if matches!(err.kind(), ErrorKind::ExpiredSignature) => {
+                Err(TokenRejection::Expired)?
             }
-        })?;
-        if Utc::now() > token_data.claims.exp {
-            Err(ApiError {
-                status: StatusCode::UNAUTHORIZED,
-                title: "Token Expired".to_string(),
-                ..Default::default()
-            })?;
+            Err(err) => Err(invalid_token(err))?,
+        };
+        let registered: Registered =
+            serde_json::from_value(value.clone()).map_err(invalid_token)?;
+        if registered.purpose.as_deref() != purpose {
+            Err(TokenRejection::NotAccepted)?;
         }
-        Ok(token_data.claims)
+        let claims: Claims<T> = serde_json::from_value(value).map_err(invalid_token)?;
+        if Utc::now() > claims.exp {
+            Err(TokenRejection::Expired)?;
+        }
+        Ok((claims, registered.sub))
     }
 
     fn decode_raw<T: DeserializeOwned>(
@@ -126,6 +413,37 @@ impl Jwt {
     }
 }
 
+/// Why [`Jwt`] rejected a token
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TokenRejection {
+    /// Not a token signed by us, e.g. a forged or garbled one
+    Invalid,
+    /// A token of another purpose, see [`Jwt::encode_purpose`]
+    NotAccepted,
+    Expired,
+}
+
+impl TokenRejection {
+    const fn error(self) -> (u16, &'static str) {
+        match self {
+            Self::Invalid => (401, "Invalid Token"),
+            Self::NotAccepted => (401, "Token Not Accepted"),
+            Self::Expired => (401, "Token Expired"),
+        }
+    }
+}
+
+impl IntoApiError for TokenRejection {
+    fn into_error_response(self) -> ApiError {
+        documented_error(self.error())
+    }
+}
+
+fn invalid_token(err: impl std::fmt::Debug) -> TokenRejection {
+    tracing::error!("Error decoding token: {:?}", err);
+    TokenRejection::Invalid
+}
+
 pub struct JwtKey {
     pub enc: EncodingKey,
     pub dec: DecodingKey,
@@ -139,22 +457,88 @@ impl JwtKey {
     }
 }
 
-// Only for OpenAPI purpose
-#[derive(Debug, JsonSchema)]
-#[allow(dead_code)]
-pub struct JwtUnauthErr {
-    #[schemars(example = 401)]
-    status: u16,
-    #[schemars(example = "Invalid Token".to_string())]
-    title: String,
+pub fn jwt_open_api(mut o: TransformPathItem) -> TransformPathItem {
+    aide::util::iter_operations_mut(o.inner_mut()).for_each(|(_, o)| {
+        require_jwt_scopes(o, &[]);
+        let responses = aide::generate::in_context(|ctx| error_responses(ctx, o, TOKEN_ERRORS));
+        add_responses(o, responses);
+    });
+    o
 }
-pub fn jwt_open_api(o: TransformPathItem) -> TransformPathItem {
-    let mut o = o.security_requirement("Json Web Token");
-    aide::util::iter_operations_mut(o.inner_mut())
-        .map(|(_, o)| TransformOperation::new(o))
-        .for_each(|o| {
-            let _ =
-                o.response_with::<401, Json<JwtUnauthErr>, _>(|r| r.description("Unauthorized"));
+
+/// Require the JWT security scheme on `operation` with `scopes` added to
+/// every security requirement mentioning it.
+pub(crate) fn require_jwt_scopes(operation: &mut Operation, scopes: &[&str]) {
+    if !operation
+        .security
+        .iter()
+        .any(|r| r.contains_key(JWT_SECURITY_SCHEME))
+    {
+        let mut requirement = SecurityRequirement::default();
+        requirement.insert(JWT_SECURITY_SCHEME.to_string(), Vec::new());
+        operation.security.push(requirement);
+    }
+    operation
+        .security
+        .iter_mut()
+        .filter_map(|r| r.get_mut(JWT_SECURITY_SCHEME))
+        .for_each(|required| {
+            for scope in scopes {
+                if !required.iter().any(|s| s == scope) {
+                    required.push(scope.to_string());
+                }
+            }
         });
-    o
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, serde_json::json};
+
+    fn jwt() -> Jwt {
+        Jwt::new(b"secret")
+    }
+
+    #[test]
+    fn numeric_subjects_are_read_as_strings() {
+        let jwt = jwt();
+        let token = jwt
+            .encode(json!({ "sub": 42 }), Duration::minutes(5))
+            .unwrap();
+        let (claims, sub) = jwt
+            .decode_registered::<serde_json::Value>(&token, None)
+            .unwrap();
+        assert_eq!(sub.as_deref(), Some("42"));
+        assert_eq!(claims.inner["sub"], 42);
+
+        let token = jwt
+            .encode(json!({ "sub": ["a"] }), Duration::minutes(5))
+            .unwrap();
+        let (_, sub) = jwt
+            .decode_registered::<serde_json::Value>(&token, None)
+            .unwrap();
+        assert_eq!(sub, None);
+    }
+
+    #[test]
+    fn payloads_may_have_a_purpose_field() {
+        let jwt = jwt();
+        let payload = json!({ "sub": "alice", "purpose": "billing" });
+        let token = jwt.encode(&payload, Duration::minutes(5)).unwrap();
+        let claims = jwt.decode::<serde_json::Value>(&token).unwrap();
+        assert_eq!(claims.inner["purpose"], "billing");
+
+        let token = jwt
+            .encode_purpose(&payload, "mfa", Duration::minutes(5))
+            .unwrap();
+        assert_eq!(
+            jwt.decode_registered::<serde_json::Value>(&token, None)
+                .err(),
+            Some(TokenRejection::NotAccepted)
+        );
+        let claims = jwt
+            .decode_purpose::<serde_json::Value>(&token, "mfa")
+            .unwrap();
+        assert_eq!(claims.inner["purpose"], "billing");
+    }
 }
@@ -1,14 +1,19 @@
 use {
-    crate::api_error::ApiError,
+    crate::api_error::{ApiError, input_error_responses},
     aide::{OperationInput, openapi::StatusCode},
     axum::extract::FromRequestParts,
     schemars::JsonSchema,
     serde::de::DeserializeOwned,
+    validator::Validate,
 };
 
 #[derive(Debug)]
 pub struct Path<T>(pub T);
 
+/// [`Path`] validated with [`Validate`]
+#[derive(Debug)]
+pub struct ValidPath<T>(pub T);
+
 impl<S, T> FromRequestParts<S> for Path<T>
 where
     T: DeserializeOwned + Send,
@@ -26,6 +31,23 @@ where
     }
 }
 
+impl<S, T> FromRequestParts<S> for ValidPath<T>
+where
+    T: DeserializeOwned + Validate + Send,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let Path(data) = Path::<T>::from_request_parts(parts, state).await?;
+        data.validate()?;
+        Ok(ValidPath(data))
+    }
+}
+
 impl<T: JsonSchema> OperationInput for Path<T> {
     fn operation_input(
         ctx: &mut aide::generate::GenContext,
@@ -38,6 +60,26 @@ impl<T: JsonSchema> OperationInput for Path<T> {
         ctx: &mut aide::generate::GenContext,
         operation: &mut aide::openapi::Operation,
     ) -> Vec<(Option<StatusCode>, aide::openapi::Response)> {
-        axum::extract::Path::<T>::inferred_early_responses(ctx, operation)
+        input_error_responses(ctx, operation, &[(400, "Axum Path Rejection")])
+    }
+}
+
+impl<T: JsonSchema> OperationInput for ValidPath<T> {
+    fn operation_input(
+        ctx: &mut aide::generate::GenContext,
+        operation: &mut aide::openapi::Operation,
+    ) {
+        axum::extract::Path::<T>::operation_input(ctx, operation)
+    }
+
+    fn inferred_early_responses(
+        ctx: &mut aide::generate::GenContext,
+        operation: &mut aide::openapi::Operation,
+    ) -> Vec<(Option<StatusCode>, aide::openapi::Response)> {
+        input_error_responses(
+            ctx,
+            operation,
+            &[(400, "Axum Path Rejection"), (400, "Validation error")],
+        )
     }
 }
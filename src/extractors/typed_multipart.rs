@@ -1,20 +1,159 @@
 use {
-    crate::prelude::ApiError,
+    super::async_validate::merge,
+    crate::{api_error::input_error_responses, prelude::ApiError},
     aide::{
         OperationInput,
-        openapi::{MediaType, RequestBody, SchemaObject, StatusCode},
+        openapi::{Encoding, MediaType, RequestBody, SchemaObject, StatusCode},
         operation::set_body,
     },
     axum::extract::FromRequest,
-    axum_typed_multipart::TryFromMultipartWithState,
+    axum_typed_multipart::{FieldData, FieldMetadata, TryFromMultipartWithState},
     derive_more::{AsMut, AsRef, Deref, DerefMut, From},
     indexmap::IndexMap,
     schemars::JsonSchema,
+    std::borrow::Cow,
+    validator::{Validate, ValidationError, ValidationErrors},
 };
 
 #[derive(Debug, Clone, Copy, Default, PartialEq, Deref, DerefMut, AsRef, AsMut, From)]
 pub struct TypedMultipart<T>(pub T);
 
+/// [`TypedMultipart`] validated with [`Validate`] and the [`FileRule`]s of its
+/// files
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deref, DerefMut, AsRef, AsMut, From)]
+pub struct ValidTypedMultipart<T>(pub T);
+
+/// Size and content type rules of the files of a multipart form, implemented
+/// by `#[validated]` from the `#[file(..)]` attributes of its fields. A form
+/// without files read as a [`ValidTypedMultipart`] implements it empty, e.g.
+/// `impl ValidateFiles for Login {}`.
+///
+/// ```ignore
+/// #[validated]
+/// #[derive(TryFromMultipart)]
+/// struct Avatar {
+///     #[file(max_size = "2MiB", content_type = "image/png", content_type = "image/jpeg")]
+///     image: FieldData<Bytes>,
+/// }
+/// ```
+pub trait ValidateFiles {
+    /// Rules of the file fields, by field name
+    fn file_rules() -> Vec<(&'static str, FileRule)> {
+        Vec::new()
+    }
+
+    fn validate_files(&self) -> Result<(), ValidationErrors> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileRule {
+    /// Largest size of each file in bytes
+    pub max_size: Option<u64>,
+    /// Accepted content types, e.g. `image/png` or `image/*`, any when empty
+    pub content_types: &'static [&'static str],
+}
+
+impl FileRule {
+    /// Add to `errors` a failure of `field` for each of its files breaking
+    /// the rule: `too_large`, `content_type` with the `received` type, or
+    /// `missing_content_type` for a part sent without one
+    pub fn check(
+        &self,
+        field: &'static str,
+        files: &impl FileField,
+        errors: &mut ValidationErrors,
+    ) {
+        for (metadata, size) in files.files() {
+            if let Some(max_size) = self.max_size.filter(|max_size| size > *max_size) {
+                let mut error = ValidationError::new("too_large");
+                error.add_param(Cow::from("limit_bytes"), &max_size);
+                errors.add(field, with_file_name(error, metadata));
+            }
+            if self.content_types.is_empty() {
+                continue;
+            }
+            let error = match metadata.content_type.as_deref() {
+                Some(content_type) if self.accepts(content_type) => continue,
+                Some(content_type) => {
+                    let mut error = ValidationError::new("content_type");
+                    error.add_param(Cow::from("received"), &content_type);
+                    error
+                }
+                None => ValidationError::new("missing_content_type"),
+            };
+            let mut error = with_file_name(error, metadata);
+            error.add_param(Cow::from("allowed"), &self.content_types);
+            errors.add(field, error);
+        }
+    }
+
+    fn accepts(&self, content_type: &str) -> bool {
+        let essence = content_type.split(';').next().unwrap_or_default().trim();
+        self.content_types
+            .iter()
+            .any(|accepted| match accepted.strip_suffix("/*") {
+                Some("*") => true,
+                Some(kind) => essence
+                    .split_once('/')
+                    .is_some_and(|(actual, _)| actual.eq_ignore_ascii_case(kind)),
+                None => essence.eq_ignore_ascii_case(accepted),
+            })
+    }
+}
+
+fn with_file_name(mut error: ValidationError, metadata: &FieldMetadata) -> ValidationError {
+    if let Some(file_name) = &metadata.file_name {
+        error.add_param(Cow::from("file_name"), file_name);
+    }
+    error
+}
+
+/// Field of a multipart form holding files, e.g. `FieldData<Bytes>`, or an
+/// `Option` or `Vec` of them
+pub trait FileField {
+    /// Metadata and size in bytes of each file
+    fn files(&self) -> Vec<(&FieldMetadata, u64)>;
+}
+
+impl<T: AsRef<[u8]>> FileField for FieldData<T> {
+    fn files(&self) -> Vec<(&FieldMetadata, u64)> {
+        vec![(&self.metadata, self.contents.as_ref().len() as u64)]
+    }
+}
+
+impl<F: FileField> FileField for Option<F> {
+    fn files(&self) -> Vec<(&FieldMetadata, u64)> {
+        self.iter().flat_map(FileField::files).collect()
+    }
+}
+
+impl<F: FileField> FileField for Vec<F> {
+    fn files(&self) -> Vec<(&FieldMetadata, u64)> {
+        self.iter().flat_map(FileField::files).collect()
+    }
+}
+
+/// Schema of an uploaded file, e.g. `#[schemars(with = "UploadedFile")]` on a
+/// `FieldData<Bytes>`. `#[validated]` uses it for the fields marked
+/// `#[file(..)]`.
+pub struct UploadedFile;
+
+impl JsonSchema for UploadedFile {
+    fn schema_name() -> Cow<'static, str> {
+        "UploadedFile".into()
+    }
+
+    fn inline_schema() -> bool {
+        true
+    }
+
+    fn json_schema(_generator: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        schemars::json_schema!({ "type": "string", "format": "binary" })
+    }
+}
+
 impl<S, T> FromRequest<S> for TypedMultipart<T>
 where
     S: Send + Sync,
@@ -31,38 +170,114 @@ where
     }
 }
 
+impl<S, T> FromRequest<S> for ValidTypedMultipart<T>
+where
+    S: Send + Sync,
+    T: TryFromMultipartWithState<S> + Validate + ValidateFiles,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: axum::extract::Request, state: &S) -> Result<Self, Self::Rejection> {
+        let TypedMultipart(data) = TypedMultipart::<T>::from_request(req, state).await?;
+        match (data.validate(), data.validate_files()) {
+            (Ok(()), Ok(())) => Ok(Self(data)),
+            (Err(errors), Ok(())) | (Ok(()), Err(errors)) => Err(errors.into()),
+            (Err(mut errors), Err(more)) => {
+                merge(&mut errors, more);
+                Err(errors.into())
+            }
+        }
+    }
+}
+
+/// Request body of a multipart form of `T`, documenting the rules of its
+/// files as the encoding of their parts
+fn multipart_body(
+    ctx: &mut aide::generate::GenContext,
+    operation: &mut aide::openapi::Operation,
+    json_schema: schemars::Schema,
+    file_rules: Vec<(&'static str, FileRule)>,
+) {
+    let encoding = file_rules
+        .into_iter()
+        .map(|(field, rule)| {
+            let mut encoding = Encoding {
+                content_type: (!rule.content_types.is_empty())
+                    .then(|| rule.content_types.join(", ")),
+                ..Default::default()
+            };
+            if let Some(max_size) = rule.max_size {
+                encoding
+                    .extensions
+                    .insert("x-max-size".to_string(), max_size.into());
+            }
+            (field.to_string(), encoding)
+        })
+        .collect();
+    set_body(
+        ctx,
+        operation,
+        RequestBody {
+            description: Some("multipart form data".into()),
+            content: IndexMap::from_iter([(
+                "multipart/form-data".into(),
+                MediaType {
+                    schema: Some(SchemaObject {
+                        json_schema,
+                        external_docs: None,
+                        example: None,
+                    }),
+                    encoding,
+                    ..Default::default()
+                },
+            )]),
+            required: true,
+            extensions: IndexMap::default(),
+        },
+    );
+}
+
 impl<T: JsonSchema> OperationInput for TypedMultipart<T> {
     fn operation_input(
         ctx: &mut aide::generate::GenContext,
         operation: &mut aide::openapi::Operation,
     ) {
         let json_schema = ctx.schema.subschema_for::<T>();
-        set_body(
-            ctx,
-            operation,
-            RequestBody {
-                description: Some("multipart form data".into()),
-                content: IndexMap::from_iter([(
-                    "multipart/form-data".into(),
-                    MediaType {
-                        schema: Some(SchemaObject {
-                            json_schema,
-                            external_docs: None,
-                            example: None,
-                        }),
-                        ..Default::default()
-                    },
-                )]),
-                required: true,
-                extensions: IndexMap::default(),
-            },
-        );
+        multipart_body(ctx, operation, json_schema, Vec::new());
+    }
+
+    fn inferred_early_responses(
+        ctx: &mut aide::generate::GenContext,
+        operation: &mut aide::openapi::Operation,
+    ) -> Vec<(Option<StatusCode>, aide::openapi::Response)> {
+        input_error_responses(ctx, operation, &[(400, "Multipart Parse Rejection")])
+    }
+}
+
+impl<T: JsonSchema + ValidateFiles> OperationInput for ValidTypedMultipart<T> {
+    fn operation_input(
+        ctx: &mut aide::generate::GenContext,
+        operation: &mut aide::openapi::Operation,
+    ) {
+        let json_schema = ctx.schema.subschema_for::<T>();
+        multipart_body(ctx, operation, json_schema, T::file_rules());
     }
 
     fn inferred_early_responses(
         ctx: &mut aide::generate::GenContext,
         operation: &mut aide::openapi::Operation,
     ) -> Vec<(Option<StatusCode>, aide::openapi::Response)> {
-        axum::extract::Multipart::inferred_early_responses(ctx, operation)
+        let mut errors = vec![
+            (400, "Multipart Parse Rejection"),
+            (400, "Validation error"),
+        ];
+        // The `max_size` of `#[file(..)]` also limits the field while reading
+        if T::file_rules()
+            .iter()
+            .any(|(_, rule)| rule.max_size.is_some())
+        {
+            errors.push((413, "Multipart Parse Rejection"));
+        }
+        input_error_responses(ctx, operation, &errors)
     }
 }
@@ -0,0 +1,142 @@
+use {
+    super::body::form_body,
+    crate::api_error::{ApiError, input_error_responses},
+    aide::{
+        OperationInput,
+        openapi::{MediaType, RequestBody, SchemaObject, StatusCode},
+        operation::set_body,
+    },
+    axum::extract::FromRequest,
+    derive_more::{AsMut, AsRef, Deref, DerefMut, From},
+    indexmap::IndexMap,
+    schemars::JsonSchema,
+    serde::de::DeserializeOwned,
+    validator::Validate,
+};
+
+/// `application/x-www-form-urlencoded` body, e.g. of an HTML form post
+#[derive(Debug, Clone, Copy, Default, Deref, DerefMut, From, AsRef, AsMut, PartialEq)]
+pub struct Form<T>(pub T);
+
+impl<S, T> FromRequest<S> for Form<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: axum::extract::Request, state: &S) -> Result<Self, Self::Rejection> {
+        let data: T = form_body(req, state).await?;
+        data.validate()?;
+        Ok(Form(data))
+    }
+}
+
+impl<T: JsonSchema> OperationInput for Form<T> {
+    fn operation_input(
+        ctx: &mut aide::generate::GenContext,
+        operation: &mut aide::openapi::Operation,
+    ) {
+        let json_schema = ctx.schema.subschema_for::<T>();
+        set_body(
+            ctx,
+            operation,
+            RequestBody {
+                description: None,
+                content: IndexMap::from_iter([(
+                    "application/x-www-form-urlencoded".into(),
+                    MediaType {
+                        schema: Some(SchemaObject {
+                            json_schema,
+                            external_docs: None,
+                            example: None,
+                        }),
+                        ..Default::default()
+                    },
+                )]),
+                required: true,
+                extensions: IndexMap::default(),
+            },
+        );
+    }
+
+    fn inferred_early_responses(
+        ctx: &mut aide::generate::GenContext,
+        operation: &mut aide::openapi::Operation,
+    ) -> Vec<(Option<StatusCode>, aide::openapi::Response)> {
+        input_error_responses(
+            ctx,
+            operation,
+            &[
+                (400, "Body Rejection"),
+                (400, "Validation error"),
+                (415, "Unsupported Media Type"),
+                (422, "Body Rejection"),
+            ],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        axum::http::{Request, StatusCode, header::CONTENT_TYPE},
+        serde::Deserialize,
+    };
+
+    const FORM: &str = "application/x-www-form-urlencoded";
+
+    #[derive(Debug, Deserialize, Validate)]
+    struct Signup {
+        #[validate(length(min = 3))]
+        login: String,
+        age: u8,
+    }
+
+    async fn post(content_type: &str, body: &'static str) -> Result<Form<Signup>, ApiError> {
+        let req = Request::post("/signup")
+            .header(CONTENT_TYPE, content_type)
+            .body(axum::body::Body::from(body))
+            .unwrap();
+        Form::from_request(req, &()).await
+    }
+
+    fn first_error(err: ApiError) -> serde_json::Value {
+        err.extensions.unwrap()["errors"][0].clone()
+    }
+
+    #[tokio::test]
+    async fn posts_are_read_and_validated() {
+        let Form(signup) = post(FORM, "login=ada%20l&age=36").await.unwrap();
+        assert_eq!(signup.login, "ada l");
+        assert_eq!(signup.age, 36);
+
+        let err = post(FORM, "login=al&age=36").await.unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+        let error = first_error(err);
+        assert_eq!(error["pointer"], "/login");
+        assert_eq!(error["code"], "length");
+    }
+
+    #[tokio::test]
+    async fn invalid_posts_are_rejected() {
+        let err = post(FORM, "login=ada&age=old").await.unwrap_err();
+        assert_eq!(err.status, StatusCode::UNPROCESSABLE_ENTITY);
+        let error = first_error(err);
+        assert_eq!(error["pointer"], "/age");
+        assert_eq!(error["code"], "type");
+
+        let err = post(FORM, "login=ada").await.unwrap_err();
+        assert_eq!(first_error(err)["code"], "required");
+
+        let err = post("application/json", r#"{"login": "ada", "age": 36}"#)
+            .await
+            .unwrap_err();
+        assert_eq!(err.status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(
+            err.detail.as_deref(),
+            Some("Expected a body of application/x-www-form-urlencoded")
+        );
+    }
+}
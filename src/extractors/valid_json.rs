@@ -1,7 +1,16 @@
 use {
-    crate::api_error::ApiError,
+    super::async_validate::{AsyncValidate, validate_with_context},
+    crate::api_error::{ApiError, input_error_responses, json_error},
     aide::{OperationInput, OperationOutput, openapi::StatusCode},
-    axum::{extract::FromRequest, response::IntoResponse},
+    axum::{
+        body::Bytes,
+        extract::{
+            FromRequest, FromRequestParts,
+            rejection::{JsonRejection, MissingJsonContentType},
+        },
+        http::{HeaderMap, header::CONTENT_TYPE},
+        response::IntoResponse,
+    },
     derive_more::{AsMut, AsRef, Deref, DerefMut, From},
     diesel::{
         deserialize::{FromSql, FromSqlRow},
@@ -44,12 +53,67 @@ where
     type Rejection = ApiError;
 
     async fn from_request(req: axum::extract::Request, state: &S) -> Result<Self, Self::Rejection> {
-        let axum::Json(data) = axum::Json::<T>::from_request(req, state).await?;
+        let data: T = json_body(req, state).await?;
         data.validate()?;
         Ok(Json(data))
     }
 }
 
+/// [`Json`] also validated with [`AsyncValidate`]
+#[derive(Debug, Clone, Copy, Default, Deref, DerefMut, From, AsRef, AsMut, PartialEq)]
+pub struct AsyncJson<T>(pub T);
+
+impl<S, T> FromRequest<S> for AsyncJson<T>
+where
+    T: DeserializeOwned + Validate + AsyncValidate<S>,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: axum::extract::Request, state: &S) -> Result<Self, Self::Rejection> {
+        let (mut parts, body) = req.into_parts();
+        let context = T::Context::from_request_parts(&mut parts, state)
+            .await
+            .map_err(Into::into)?;
+        let data: T = json_body(axum::extract::Request::from_parts(parts, body), state).await?;
+        validate_with_context(&data, &context).await?;
+        Ok(AsyncJson(data))
+    }
+}
+
+/// Deserialize the body, tracking the path to the failing field for the
+/// error
+async fn json_body<T, S>(req: axum::extract::Request, state: &S) -> Result<T, ApiError>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    if !json_content_type(req.headers()) {
+        Err(JsonRejection::from(MissingJsonContentType::default()))?;
+    }
+    let bytes = Bytes::from_request(req, state)
+        .await
+        .map_err(JsonRejection::from)?;
+    json_from_slice(&bytes)
+}
+
+pub(crate) fn json_from_slice<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, ApiError> {
+    serde_json::from_slice(bytes).map_err(|err| json_error::<T>(bytes, err))
+}
+
+/// `application/json`, or any `application/*+json`
+fn json_content_type(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers.get(CONTENT_TYPE).and_then(|h| h.to_str().ok()) else {
+        return false;
+    };
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    let Some((kind, subtype)) = essence.split_once('/') else {
+        return false;
+    };
+    kind.eq_ignore_ascii_case("application")
+        && (subtype.eq_ignore_ascii_case("json") || subtype.to_ascii_lowercase().ends_with("+json"))
+}
+
 impl<T: Serialize> IntoResponse for Json<T> {
     fn into_response(self) -> axum::response::Response {
         axum::Json(self.0).into_response()
@@ -68,9 +132,34 @@ impl<T: JsonSchema> OperationInput for Json<T> {
         ctx: &mut aide::generate::GenContext,
         operation: &mut aide::openapi::Operation,
     ) -> Vec<(Option<StatusCode>, aide::openapi::Response)> {
-        <axum::Json<T> as OperationInput>::inferred_early_responses(ctx, operation)
+        input_error_responses(
+            ctx,
+            operation,
+            &[
+                (400, "Axum Json Rejection"),
+                (400, "Validation error"),
+                (415, "Axum Json Rejection"),
+                (422, "Axum Json Rejection"),
+            ],
+        )
     }
 }
+impl<T: JsonSchema> OperationInput for AsyncJson<T> {
+    fn operation_input(
+        ctx: &mut aide::generate::GenContext,
+        operation: &mut aide::openapi::Operation,
+    ) {
+        Json::<T>::operation_input(ctx, operation)
+    }
+
+    fn inferred_early_responses(
+        ctx: &mut aide::generate::GenContext,
+        operation: &mut aide::openapi::Operation,
+    ) -> Vec<(Option<StatusCode>, aide::openapi::Response)> {
+        Json::<T>::inferred_early_responses(ctx, operation)
+    }
+}
+
 impl<T: JsonSchema> OperationOutput for Json<T> {
     type Inner = <axum::Json<T> as OperationOutput>::Inner;
 
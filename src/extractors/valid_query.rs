@@ -1,12 +1,15 @@
 use {
-    crate::api_error::ApiError,
+    super::async_validate::{AsyncValidate, validate_with_context},
+    crate::api_error::{ApiError, from_form, input_error_responses},
     aide::{OperationInput, openapi::StatusCode},
-    axum::extract::FromRequestParts,
+    axum::{extract::FromRequestParts, http},
+    derive_more::{AsMut, AsRef, Deref, DerefMut, From},
     schemars::JsonSchema,
     serde::de::DeserializeOwned,
     validator::Validate,
 };
 
+#[derive(Debug, Clone, Copy, Default, Deref, DerefMut, From, AsRef, AsMut, PartialEq)]
 pub struct Query<T>(pub T);
 
 impl<S, T> FromRequestParts<S> for Query<T>
@@ -20,13 +23,53 @@ where
         parts: &mut axum::http::request::Parts,
         state: &S,
     ) -> Result<Self, Self::Rejection> {
-        let axum::extract::Query(data) =
-            axum::extract::Query::<T>::from_request_parts(parts, state).await?;
+        let data: T = query(parts)?;
         data.validate()?;
         Ok(Query(data))
     }
 }
 
+/// [`Query`] also validated with [`AsyncValidate`]
+#[derive(Debug, Clone, Copy, Default, Deref, DerefMut, From, AsRef, AsMut, PartialEq)]
+pub struct AsyncQuery<T>(pub T);
+
+impl<S, T> FromRequestParts<S> for AsyncQuery<T>
+where
+    T: DeserializeOwned + Validate + AsyncValidate<S>,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let data: T = query(parts)?;
+        let context = T::Context::from_request_parts(parts, state)
+            .await
+            .map_err(Into::into)?;
+        validate_with_context(&data, &context).await?;
+        Ok(AsyncQuery(data))
+    }
+}
+
+/// The query string deserialized, failures name the field at fault
+fn query<T: DeserializeOwned>(parts: &http::request::Parts) -> Result<T, ApiError> {
+    let query = parts.uri.query().unwrap_or_default();
+    from_form(query.as_bytes()).map_err(|error| {
+        ApiError {
+            status: http::StatusCode::BAD_REQUEST,
+            title: "Axum Query Rejection".to_string(),
+            detail: Some(format!(
+                "Failed to deserialize query string: {}",
+                error.message.as_deref().unwrap_or_default()
+            )),
+            ..Default::default()
+        }
+        .with_field_errors(vec![error])
+    })
+}
+
 impl<T: JsonSchema> OperationInput for Query<T> {
     fn operation_input(
         ctx: &mut aide::generate::GenContext,
@@ -39,6 +82,26 @@ impl<T: JsonSchema> OperationInput for Query<T> {
         ctx: &mut aide::generate::GenContext,
         operation: &mut aide::openapi::Operation,
     ) -> Vec<(Option<StatusCode>, aide::openapi::Response)> {
-        axum::extract::Query::<T>::inferred_early_responses(ctx, operation)
+        input_error_responses(
+            ctx,
+            operation,
+            &[(400, "Axum Query Rejection"), (400, "Validation error")],
+        )
+    }
+}
+
+impl<T: JsonSchema> OperationInput for AsyncQuery<T> {
+    fn operation_input(
+        ctx: &mut aide::generate::GenContext,
+        operation: &mut aide::openapi::Operation,
+    ) {
+        Query::<T>::operation_input(ctx, operation)
+    }
+
+    fn inferred_early_responses(
+        ctx: &mut aide::generate::GenContext,
+        operation: &mut aide::openapi::Operation,
+    ) -> Vec<(Option<StatusCode>, aide::openapi::Response)> {
+        Query::<T>::inferred_early_responses(ctx, operation)
     }
 }
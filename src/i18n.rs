@@ -0,0 +1,384 @@
+use {
+    crate::{
+        auth::cookie::JwtCookie,
+        extractors::{Jwt, request_token},
+    },
+    axum::{
+        extract::Request,
+        http::{self, header::ACCEPT_LANGUAGE},
+    },
+    fluent_bundle::{FluentArgs, FluentResource, FluentValue, concurrent::FluentBundle},
+    std::{borrow::Cow, path::Path, sync::OnceLock},
+    unic_langid::LanguageIdentifier,
+    validator::{ValidationErrors, ValidationErrorsKind},
+};
+
+static TRANSLATIONS: OnceLock<Translations> = OnceLock::new();
+
+/// Fluent messages for error titles and validation failures.
+///
+/// Titles are looked up by their [`code`](crate::api_error::ApiError::code),
+/// e.g. `token-expired` for `Token Expired`, validation failures by their code
+/// prefixed with `validation-`, e.g. `validation-length` with the params of the
+/// failure as arguments.
+pub struct Translations {
+    bundles: Vec<FluentBundle<FluentResource>>,
+    default: LanguageIdentifier,
+    claim: String,
+}
+
+impl Translations {
+    /// Load every `{dir}/{locale}/*.ftl`, e.g. `locales/fr-FR/errors.ftl`
+    pub fn load(dir: impl AsRef<Path>, default: &str) -> Result<Self, eyre::Error> {
+        let default: LanguageIdentifier = default.parse()?;
+        let mut bundles = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let locale: LanguageIdentifier = entry.file_name().to_string_lossy().parse()?;
+            let mut bundle = FluentBundle::new_concurrent(vec![locale]);
+            // Isolation marks would end up in JSON strings
+            bundle.set_use_isolating(false);
+            for file in std::fs::read_dir(entry.path())? {
+                let path = file?.path();
+                if path.extension().is_none_or(|ext| ext != "ftl") {
+                    continue;
+                }
+                let source = std::fs::read_to_string(&path)?;
+                let resource = FluentResource::try_new(source).map_err(|(_, errors)| {
+                    eyre::eyre!("Invalid Fluent file {}: {:?}", path.display(), errors)
+                })?;
+                bundle.add_resource(resource).map_err(|errors| {
+                    eyre::eyre!("Conflicting messages in {}: {:?}", path.display(), errors)
+                })?;
+            }
+            bundles.push(bundle);
+        }
+        if !bundles.iter().any(|b| b.locales[0] == default) {
+            eyre::bail!("No translations for the default locale `{default}`");
+        }
+        Ok(Self {
+            bundles,
+            default,
+            claim: "locale".to_string(),
+        })
+    }
+
+    /// Claim of the token holding the user's locale, preferred over
+    /// `Accept-Language`
+    pub fn claim(mut self, claim: impl Into<String>) -> Self {
+        self.claim = claim.into();
+        self
+    }
+
+    /// Use the translations for every error response, once per process
+    pub(crate) fn install(self) -> Result<(), eyre::Error> {
+        TRANSLATIONS
+            .set(self)
+            .map_err(|_| eyre::eyre!("Translations are already installed"))
+    }
+
+    /// The available locale closest to the first satisfiable of `requested`
+    fn negotiate(&self, requested: &[LanguageIdentifier]) -> LanguageIdentifier {
+        let available = || self.bundles.iter().map(|b| &b.locales[0]);
+        requested
+            .iter()
+            .find_map(|req| {
+                available()
+                    .find(|locale| *locale == req)
+                    .or_else(|| available().find(|locale| locale.language == req.language))
+            })
+            .unwrap_or(&self.default)
+            .clone()
+    }
+
+    fn bundle(&self, locale: &LanguageIdentifier) -> Option<&FluentBundle<FluentResource>> {
+        self.bundles.iter().find(|b| &b.locales[0] == locale)
+    }
+
+    /// Locale of the request, from the claim of its token or else from
+    /// `Accept-Language`
+    fn request_locale(&self, parts: &http::request::Parts) -> LanguageIdentifier {
+        let claimed = parts
+            .extensions
+            .get::<Jwt>()
+            .zip(request_token(parts).ok().flatten())
+            .and_then(|(jwt, token)| jwt.decode::<serde_json::Value>(&token).ok())
+            .and_then(|claims| claims.inner.get(&self.claim)?.as_str()?.parse().ok());
+        let requested = match claimed {
+            Some(locale) => vec![locale],
+            None => accept_language(parts),
+        };
+        self.negotiate(&requested)
+    }
+
+    fn translate(
+        &self,
+        locale: &LanguageIdentifier,
+        key: &str,
+        args: Option<&FluentArgs>,
+    ) -> Option<String> {
+        let bundle = self.bundle(locale)?;
+        let pattern = bundle.get_message(key)?.value()?;
+        let mut errors = Vec::new();
+        let message = bundle.format_pattern(pattern, args, &mut errors);
+        if !errors.is_empty() {
+            tracing::warn!("Error formatting message `{}`: {:?}", key, errors);
+        }
+        Some(message.into_owned())
+    }
+
+    fn localize_validation(&self, errors: &mut ValidationErrors, locale: &LanguageIdentifier) {
+        for kind in errors.errors_mut().values_mut() {
+            match kind {
+                ValidationErrorsKind::Field(failures) => {
+                    for failure in failures {
+                        let mut args = FluentArgs::new();
+                        for (name, value) in &failure.params {
+                            let value = match value {
+                                serde_json::Value::Number(n) => {
+                                    FluentValue::from(n.as_f64().unwrap_or_default())
+                                }
+                                serde_json::Value::String(s) => FluentValue::from(s.clone()),
+                                other => FluentValue::from(other.to_string()),
+                            };
+                            args.set(name.to_string(), value);
+                        }
+                        let key = format!("validation-{}", failure.code);
+                        if let Some(message) = self.translate(locale, &key, Some(&args)) {
+                            failure.message = Some(Cow::Owned(message));
+                        }
+                    }
+                }
+                ValidationErrorsKind::Struct(nested) => self.localize_validation(nested, locale),
+                ValidationErrorsKind::List(items) => {
+                    for nested in items.values_mut() {
+                        self.localize_validation(nested, locale);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Locale of a request, only resolved when an error is translated since it
+/// may decode the request's token
+pub(crate) struct RequestLocale {
+    /// What [`request_locale`] reads of the request
+    parts: http::request::Parts,
+    locale: OnceLock<Option<LanguageIdentifier>>,
+}
+
+impl RequestLocale {
+    /// `None` without translations installed
+    pub(crate) fn of(req: &Request) -> Option<Self> {
+        TRANSLATIONS.get()?;
+        let (mut parts, ()) = http::Request::new(()).into_parts();
+        parts.method = req.method().clone();
+        parts.headers = req.headers().clone();
+        if let Some(jwt) = req.extensions().get::<Jwt>() {
+            parts.extensions.insert(jwt.clone());
+        }
+        if let Some(cookie) = req.extensions().get::<JwtCookie>() {
+            parts.extensions.insert(cookie.clone());
+        }
+        Some(Self {
+            parts,
+            locale: OnceLock::new(),
+        })
+    }
+
+    pub(crate) fn get(&self) -> Option<&LanguageIdentifier> {
+        self.locale
+            .get_or_init(|| request_locale(&self.parts))
+            .as_ref()
+    }
+}
+
+/// Locale of the request, `None` without translations installed
+fn request_locale(parts: &http::request::Parts) -> Option<LanguageIdentifier> {
+    Some(TRANSLATIONS.get()?.request_locale(parts))
+}
+
+/// Locales of `Accept-Language`, by decreasing preference
+fn accept_language(parts: &http::request::Parts) -> Vec<LanguageIdentifier> {
+    let Some(header) = parts
+        .headers
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|h| h.to_str().ok())
+    else {
+        return Vec::new();
+    };
+    let mut locales: Vec<(f32, LanguageIdentifier)> = header
+        .split(',')
+        .filter_map(|item| {
+            let mut item = item.split(';');
+            let locale = item.next()?.trim().parse().ok()?;
+            let quality = item
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse().ok())?;
+            // `q=0` marks a locale as not acceptable
+            (quality > 0.0).then_some((quality, locale))
+        })
+        .collect();
+    locales.sort_by(|a, b| b.0.total_cmp(&a.0));
+    locales.into_iter().map(|(_, locale)| locale).collect()
+}
+
+/// The message `key` in `locale`, if translated
+pub(crate) fn translate(
+    locale: &LanguageIdentifier,
+    key: &str,
+    args: Option<&FluentArgs>,
+) -> Option<String> {
+    TRANSLATIONS.get()?.translate(locale, key, args)
+}
+
+/// Replace the message of every failure with its translation in `locale`
+pub(crate) fn localize_validation(errors: &mut ValidationErrors, locale: &LanguageIdentifier) {
+    if let Some(translations) = TRANSLATIONS.get() {
+        translations.localize_validation(errors, locale);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*, axum::http::header::AUTHORIZATION, chrono::Duration, uuid::Uuid,
+        validator::ValidationError,
+    };
+
+    /// Translations loaded from a directory of `.ftl` files
+    fn translations(files: &[(&str, &str)]) -> Translations {
+        let dir = std::env::temp_dir().join(format!("axum-api-i18n-{}", Uuid::new_v4()));
+        for (path, source) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, source).unwrap();
+        }
+        let translations = Translations::load(&dir, "en-US");
+        std::fs::remove_dir_all(&dir).unwrap();
+        translations.unwrap()
+    }
+
+    fn english_and_french() -> Translations {
+        translations(&[
+            (
+                "en-US/errors.ftl",
+                "token-expired = Token Expired\nvalidation-length = At least { $min } characters\n",
+            ),
+            (
+                "fr-FR/errors.ftl",
+                "token-expired = Jeton expiré\nvalidation-length = Au moins { $min } caractères\n",
+            ),
+            ("fr-FR/README.md", "Not a Fluent file"),
+        ])
+    }
+
+    fn locale(locale: &str) -> LanguageIdentifier {
+        locale.parse().unwrap()
+    }
+
+    fn request(headers: &[(http::HeaderName, &str)], jwt: Option<Jwt>) -> http::request::Parts {
+        let mut req = http::Request::builder();
+        for (name, value) in headers {
+            req = req.header(name, *value);
+        }
+        let (mut parts, ()) = req.body(()).unwrap().into_parts();
+        if let Some(jwt) = jwt {
+            parts.extensions.insert(jwt);
+        }
+        parts
+    }
+
+    #[test]
+    fn accept_language_is_read_by_quality_without_unacceptable_locales() {
+        let parts = request(
+            &[(
+                ACCEPT_LANGUAGE,
+                "de;q=0.5, fr-CA, en-US;q=0, not a locale!, it;q=0.8",
+            )],
+            None,
+        );
+        assert_eq!(
+            accept_language(&parts),
+            [locale("fr-CA"), locale("it"), locale("de")]
+        );
+        assert!(accept_language(&request(&[], None)).is_empty());
+    }
+
+    #[test]
+    fn locales_are_negotiated_by_language_then_default() {
+        let translations = english_and_french();
+        let negotiate = |requested: &[&str]| {
+            let requested: Vec<_> = requested.iter().map(|l| locale(l)).collect();
+            translations.negotiate(&requested).to_string()
+        };
+        assert_eq!(negotiate(&["fr-FR"]), "fr-FR");
+        assert_eq!(negotiate(&["de", "fr-CA"]), "fr-FR");
+        assert_eq!(negotiate(&["de"]), "en-US");
+        assert_eq!(negotiate(&[]), "en-US");
+    }
+
+    #[test]
+    fn unacceptable_locales_are_not_fallen_back_on() {
+        let translations = english_and_french();
+        let parts = request(&[(ACCEPT_LANGUAGE, "de, fr;q=0")], None);
+        assert_eq!(translations.request_locale(&parts), locale("en-US"));
+    }
+
+    #[test]
+    fn token_claim_overrides_accept_language() {
+        let translations = english_and_french().claim("lang");
+        let jwt = Jwt::new(b"secret");
+        let token = jwt
+            .encode(serde_json::json!({ "lang": "fr-FR" }), Duration::minutes(5))
+            .unwrap();
+        let bearer = format!("Bearer {token}");
+        let parts = request(
+            &[(ACCEPT_LANGUAGE, "en-US"), (AUTHORIZATION, &bearer)],
+            Some(jwt.clone()),
+        );
+        assert_eq!(translations.request_locale(&parts), locale("fr-FR"));
+
+        // Tokens that don't decode leave it to `Accept-Language`
+        let parts = request(
+            &[(ACCEPT_LANGUAGE, "en-US"), (AUTHORIZATION, "Bearer forged")],
+            Some(jwt),
+        );
+        assert_eq!(translations.request_locale(&parts), locale("en-US"));
+    }
+
+    #[test]
+    fn validation_messages_are_translated_with_their_params() {
+        let translations = english_and_french();
+        let mut length = ValidationError::new("length");
+        length.add_param(Cow::from("min"), &3);
+        let mut errors = ValidationErrors::new();
+        errors.add("name", length);
+        errors.add("email", ValidationError::new("email"));
+
+        translations.localize_validation(&mut errors, &locale("fr-FR"));
+        let fields = errors.field_errors();
+        assert_eq!(
+            fields["name"][0].message.as_deref(),
+            Some("Au moins 3 caractères")
+        );
+        assert_eq!(fields["email"][0].message, None);
+        assert_eq!(
+            translations.translate(&locale("fr-FR"), "token-expired", None),
+            Some("Jeton expiré".to_string())
+        );
+    }
+
+    #[test]
+    fn the_default_locale_must_be_translated() {
+        let dir = std::env::temp_dir().join(format!("axum-api-i18n-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("fr-FR")).unwrap();
+        let loaded = Translations::load(&dir, "en-US");
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(loaded.is_err());
+    }
+}
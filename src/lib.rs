@@ -1,13 +1,28 @@
 pub mod aide_ext;
 pub mod api_error;
-mod auth;
+pub mod auth;
 pub mod diesel_otel;
 pub mod extractors;
+#[cfg(feature = "i18n")]
+pub mod i18n;
 pub mod prelude;
 mod scalar;
+pub mod tenant;
 
 use {
-    crate::{diesel_otel::OtelInstrument, extractors::Jwt, scalar::Scalar},
+    crate::{
+        api_error::{ErrorCatalogue, problem_context},
+        auth::{
+            api_key::{API_KEY_SECURITY_SCHEME, ApiKeyConfig},
+            cookie::{COOKIE_SECURITY_SCHEME, JwtCookie, add_cookie_alternatives},
+            revocation::RevocationBackend,
+            throttle::{ThrottleConfig, TrustedProxies},
+        },
+        diesel_otel::OtelInstrument,
+        extractors::{Database, JWT_SECURITY_SCHEME, Jwt},
+        scalar::Scalar,
+        tenant::{TenantConfig, resolve_tenant},
+    },
     aide::{
         axum::ApiRouter,
         openapi::{OpenApi, SecurityScheme},
@@ -17,10 +32,27 @@ use {
     derive_builder::Builder,
     diesel_async::{AsyncConnection, AsyncMigrationHarness, AsyncPgConnection},
     diesel_migrations::{EmbeddedMigrations, MigrationHarness},
-    std::{net::SocketAddr, sync::Arc},
+    std::{
+        net::{IpAddr, SocketAddr},
+        sync::Arc,
+    },
     tokio::net::{TcpListener, ToSocketAddrs},
 };
-pub use {extractors::jwt_open_api, init_tracing_opentelemetry::TracingConfig};
+pub use {
+    axum_api_macros::{ApiError, validated},
+    extractors::jwt_open_api,
+    init_tracing_opentelemetry::TracingConfig,
+};
+
+/// Used by the code generated by the derive macros
+#[doc(hidden)]
+pub mod __private {
+    pub use {
+        aide,
+        axum::{self, http::StatusCode},
+        schemars, serde_json, validator,
+    };
+}
 
 #[derive(Builder)]
 #[builder(pattern = "owned", name = "Config", build_fn(name = "make_server"))]
@@ -40,28 +72,110 @@ pub struct Server<A: ToSocketAddrs> {
     migratons: Option<EmbeddedMigrations>,
     #[builder(default = TracingConfig::development())]
     otel_config: TracingConfig,
+    /// Where revoked tokens are kept, revocation checks are skipped if unset
+    #[builder(default, setter(strip_option))]
+    revocation: Option<RevocationBackend>,
+    /// Also accept the token from a cookie, with CSRF protection
+    #[builder(default, setter(strip_option))]
+    jwt_cookie: Option<JwtCookie>,
+    /// Authenticate machine clients with API keys
+    #[builder(default, setter(strip_option))]
+    api_keys: Option<ApiKeyConfig>,
+    /// Brute-force protection of tokens and login routes
+    #[builder(default, setter(strip_option))]
+    throttle: Option<ThrottleConfig>,
+    /// Reverse proxies in front of the server, trusted to tell the client's
+    /// address in `X-Forwarded-For` or `X-Real-IP`
+    #[builder(default, setter(into))]
+    trusted_proxies: Vec<IpAddr>,
+    /// Resolve a tenant per request, for the `Tenant` and `TenantDatabase`
+    /// extractors
+    #[builder(default, setter(strip_option))]
+    tenancy: Option<TenantConfig>,
+    /// Error types given stable `type` URIs and documented in OpenAPI
+    #[builder(default, setter(strip_option))]
+    error_catalogue: Option<ErrorCatalogue>,
+    /// Translations of error titles and validation messages, negotiated per
+    /// request
+    #[cfg(feature = "i18n")]
+    #[builder(default, setter(strip_option))]
+    translations: Option<i18n::Translations>,
 }
 
 impl<A: ToSocketAddrs> Server<A> {
     pub async fn serve(self) -> Result<(), eyre::Error> {
         let _guard = self.otel_config.init_subscriber()?;
 
+        // Diesel
+        let database = match &self.pg_url {
+            Some(pg_url) => {
+                let database = AsyncPgConnection::establish(pg_url).await?;
+                let mut database = AsyncMigrationHarness::new(database);
+                if let Some(migrations) = self.migratons {
+                    database
+                        .run_pending_migrations(migrations)
+                        .expect("Migration failed");
+                }
+                let mut database = database.into_inner();
+                database.set_instrumentation(OtelInstrument);
+                Some(Database::new(Arc::new(database)))
+            }
+            None => None,
+        };
+
+        let catalogue = self.error_catalogue.map(Arc::new);
+        #[cfg(feature = "i18n")]
+        if let Some(translations) = self.translations {
+            translations.install()?;
+        }
+
         let app = {
             let mut api = OpenApi::default();
             aide::generate::all_error_responses(true);
-            let mut app = self
-                .app
-                .finish_api_with(&mut api, |o| {
-                    o.title("Axum Api").security_scheme(
-                        "Json Web Token",
-                        SecurityScheme::Http {
-                            scheme: "Bearer".to_string(),
-                            bearer_format: None,
-                            description: Some("Bearer token using JWT".to_string()),
-                            extensions: Default::default(),
-                        },
-                    )
-                })
+            let mut app = self.app.finish_api_with(&mut api, |mut o| {
+                if let Some(catalogue) = &catalogue {
+                    catalogue.document(o.inner_mut());
+                }
+                let o = o.title("Axum Api").security_scheme(
+                    JWT_SECURITY_SCHEME,
+                    SecurityScheme::Http {
+                        scheme: "Bearer".to_string(),
+                        bearer_format: None,
+                        description: Some("Bearer token using JWT".to_string()),
+                        extensions: Default::default(),
+                    },
+                );
+                let o = match &self.jwt_cookie {
+                    Some(cookie) => {
+                        o.security_scheme(COOKIE_SECURITY_SCHEME, cookie.security_scheme())
+                    }
+                    None => o,
+                };
+                match &self.api_keys {
+                    Some(api_keys) => {
+                        o.security_scheme(API_KEY_SECURITY_SCHEME, api_keys.security_scheme())
+                    }
+                    None => o,
+                }
+            });
+            app = app.layer(axum::middleware::from_fn_with_state(
+                catalogue,
+                problem_context,
+            ));
+            // Tenancy, inside the OTEL span and with the Jwt and its cookie
+            // available
+            if let Some(tenancy) = self.tenancy {
+                let tenancy = tenancy.into_tenancy(self.pg_url.as_deref())?;
+                app = app.layer(axum::middleware::from_fn_with_state(
+                    tenancy,
+                    resolve_tenant,
+                ));
+            }
+            if let Some(cookie) = &self.jwt_cookie {
+                add_cookie_alternatives(&mut api);
+                app = app.layer(Extension(cookie.clone()));
+            }
+            let mut app = app
                 // OTEL
                 .layer(OtelInResponseLayer::default())
                 .layer(OtelAxumLayer::default().try_extract_client_ip(true))
@@ -71,20 +185,32 @@ impl<A: ToSocketAddrs> Server<A> {
                 // CORS
                 .layer(cors_layer())
                 // Jwt
-                .layer(Extension(Jwt::new(self.jwt_secret.as_bytes())));
+                .layer(Extension(Jwt::new(self.jwt_secret.as_bytes())))
+                // Client address
+                .layer(Extension(TrustedProxies(self.trusted_proxies.into())));
 
-            // Diesel
-            if let Some(pg_url) = &self.pg_url {
-                let database = AsyncPgConnection::establish(pg_url).await?;
-                let mut database = AsyncMigrationHarness::new(database);
-                if let Some(migrations) = self.migratons {
-                    database
-                        .run_pending_migrations(migrations)
-                        .expect("Migration failed");
-                }
-                let mut database = database.into_inner();
-                database.set_instrumentation(OtelInstrument);
-                app = app.layer(Extension(Arc::new(database)))
+            // Token revocation
+            if let Some(revocation) = self.revocation {
+                let revocations = revocation.into_revocations(database.as_ref()).await?;
+                revocations.spawn_pruning();
+                app = app.layer(Extension(revocations));
+            }
+
+            // API keys
+            if let Some(api_keys) = self.api_keys {
+                let api_keys = api_keys.into_api_keys(database.as_ref()).await?;
+                app = app.layer(Extension(api_keys));
+            }
+
+            // Brute-force protection
+            if let Some(throttle) = self.throttle {
+                let throttle = throttle.into_throttle(database.as_ref()).await?;
+                throttle.spawn_pruning();
+                app = app.layer(Extension(throttle));
+            }
+
+            if let Some(database) = database {
+                app = app.layer(Extension(database.into_inner()))
             };
 
             app
@@ -1,8 +1,21 @@
 pub use {
     crate::{
-        api_error::{ApiError, IntoApiError},
+        ApiError,
+        api_error::{
+            ApiError, ApiResult, ErrorCatalogue, ErrorType, FieldError, Internal, IntoApiError,
+        },
+        auth::{
+            api_key::{ApiKey, ApiKeys},
+            cookie::JwtCookie,
+            policy::{DenyAs, LoadResource, Policy, authorize, load_authorized},
+            revocation::Revocations,
+            roles::{Permission, RequirePermission, RequireRole, Role},
+            throttle::{ClientIp, FailureKey, Throttle},
+        },
         diesel_otel::RunQueryDsl,
         extractors::*,
+        tenant::{Tenant, TenantDatabase},
+        validated,
     },
     aide::{
         NoApi, OperationInput, OperationOutput, UseApi, WithApi,
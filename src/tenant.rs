@@ -0,0 +1,287 @@
+use {
+    crate::{
+        api_error::ApiError,
+        diesel_otel::{OtelInstrument, RunQueryDsl},
+        extractors::{Jwt, request_token},
+    },
+    aide::OperationInput,
+    async_trait::async_trait,
+    axum::{
+        extract::{FromRequestParts, Request, State},
+        http::{self, HeaderName, StatusCode, header::HOST},
+        middleware::Next,
+        response::{IntoResponse, Response},
+    },
+    diesel::{ConnectionResult, sql_types::Text},
+    diesel_async::{
+        AsyncConnection, AsyncPgConnection,
+        pooled_connection::{
+            AsyncDieselConnectionManager, ManagerConfig,
+            deadpool::{Object, Pool},
+        },
+    },
+    std::{pin::Pin, sync::Arc},
+    tracing_opentelemetry::OpenTelemetrySpanExt,
+};
+
+tokio::task_local! {
+    static CURRENT_TENANT: Tenant;
+}
+
+/// Id of the tenant resolved for the current request, if any
+pub(crate) fn current_tenant() -> Option<Tenant> {
+    CURRENT_TENANT.try_with(Clone::clone).ok()
+}
+
+/// Where the tenant of a request is read from
+///
+/// Only [`TenantStrategy::Claim`] ties the tenant to the caller's token. A
+/// tenant from a header or subdomain is whatever the client sends, so
+/// handlers must check the authenticated user belongs to it.
+pub enum TenantStrategy {
+    /// String claim of the token, the token is only decoded here, it is
+    /// still up to the handler to authenticate the request
+    Claim(String),
+    /// Not checked against the authenticated user
+    Header(HeaderName),
+    /// First label of the `Host` header under this domain, e.g. `acme` for
+    /// `acme.example.com` under `example.com`. Not checked against the
+    /// authenticated user.
+    Subdomain(String),
+    Custom(Arc<dyn TenantResolver>),
+}
+
+/// Application side tenant lookup, e.g. of custom domains
+#[async_trait]
+pub trait TenantResolver: Send + Sync + 'static {
+    async fn resolve(&self, parts: &http::request::Parts) -> Result<Option<String>, ApiError>;
+}
+
+/// How connections of [`TenantDatabase`] are scoped to the tenant
+pub enum TenantIsolation {
+    /// `search_path` set to the schema `{prefix}{tenant}`
+    Schema { prefix: String },
+    /// Session variable set to the tenant id, for row-level security policies
+    /// reading it through `current_setting`
+    SessionVariable(String),
+}
+
+pub struct TenantConfig {
+    pub strategy: TenantStrategy,
+    pub isolation: TenantIsolation,
+    /// Connections of the pool backing [`TenantDatabase`]
+    pub pool_size: usize,
+}
+
+impl TenantConfig {
+    pub fn new(strategy: TenantStrategy) -> Self {
+        Self {
+            strategy,
+            isolation: TenantIsolation::SessionVariable("app.tenant_id".to_string()),
+            pool_size: 16,
+        }
+    }
+
+    pub(crate) fn into_tenancy(self, pg_url: Option<&str>) -> Result<Arc<Tenancy>, eyre::Error> {
+        let pg_url =
+            pg_url.ok_or_else(|| eyre::eyre!("Tenant scoped database access requires `pg_url`"))?;
+        let mut manager_config = ManagerConfig::default();
+        manager_config.custom_setup = Box::new(establish);
+        let manager = AsyncDieselConnectionManager::<AsyncPgConnection>::new_with_config(
+            pg_url,
+            manager_config,
+        );
+        let pool = Pool::builder(manager).max_size(self.pool_size).build()?;
+        Ok(Arc::new(Tenancy {
+            strategy: self.strategy,
+            isolation: self.isolation,
+            pool,
+        }))
+    }
+}
+
+fn establish(
+    url: &str,
+) -> Pin<Box<dyn Future<Output = ConnectionResult<AsyncPgConnection>> + Send + '_>> {
+    Box::pin(async move {
+        let mut conn = AsyncPgConnection::establish(url).await?;
+        conn.set_instrumentation(OtelInstrument);
+        Ok(conn)
+    })
+}
+
+pub(crate) struct Tenancy {
+    strategy: TenantStrategy,
+    isolation: TenantIsolation,
+    pool: Pool<AsyncPgConnection>,
+}
+
+impl Tenancy {
+    async fn resolve(&self, parts: &http::request::Parts) -> Result<Option<String>, ApiError> {
+        let header = |name: &HeaderName| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|h| h.to_str().ok())
+                .map(str::to_string)
+        };
+        let tenant = match &self.strategy {
+            TenantStrategy::Claim(claim) => {
+                let Some(jwt) = parts.extensions.get::<Jwt>() else {
+                    return Ok(None);
+                };
+                let Ok(Some(token)) = request_token(parts) else {
+                    return Ok(None);
+                };
+                jwt.decode::<serde_json::Value>(&token)
+                    .ok()
+                    .and_then(|claims| claims.inner.get(claim)?.as_str().map(str::to_string))
+            }
+            TenantStrategy::Header(name) => header(name),
+            TenantStrategy::Subdomain(domain) => header(&HOST).and_then(|host| {
+                let host = host.split(':').next()?;
+                let subdomain = host.strip_suffix(domain.as_str())?.strip_suffix('.')?;
+                (!subdomain.contains('.')).then(|| subdomain.to_string())
+            }),
+            TenantStrategy::Custom(resolver) => resolver.resolve(parts).await?,
+        };
+        match tenant {
+            Some(tenant) if !is_valid_tenant(&tenant) => Err(ApiError {
+                status: StatusCode::BAD_REQUEST,
+                title: "Invalid Tenant".to_string(),
+                ..Default::default()
+            }),
+            tenant => Ok(tenant),
+        }
+    }
+}
+
+/// Tenant ids end up in schema names and settings, keep them to identifiers
+fn is_valid_tenant(tenant: &str) -> bool {
+    !tenant.is_empty()
+        && tenant.len() <= 48
+        && tenant
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Resolve the tenant of the request, record it in the request span and keep
+/// it for the spans made while handling the request
+pub(crate) async fn resolve_tenant(
+    State(tenancy): State<Arc<Tenancy>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = req.into_parts();
+    let tenant = match tenancy.resolve(&parts).await {
+        Ok(tenant) => tenant.map(Tenant),
+        Err(err) => return err.into_response(),
+    };
+    parts.extensions.insert(tenancy);
+    match tenant {
+        Some(tenant) => {
+            tracing::Span::current().set_attribute("tenant.id", tenant.0.clone());
+            parts.extensions.insert(tenant.clone());
+            let req = Request::from_parts(parts, body);
+            CURRENT_TENANT.scope(tenant, next.run(req)).await
+        }
+        None => next.run(Request::from_parts(parts, body)).await,
+    }
+}
+
+/// Tenant of the request, rejected with 400 if none could be resolved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tenant(pub String);
+
+impl OperationInput for Tenant {}
+impl<S: Sync> FromRequestParts<S> for Tenant {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        _: &S,
+    ) -> Result<Self, Self::Rejection> {
+        if parts.extensions.get::<Arc<Tenancy>>().is_none() {
+            Err(not_configured())?;
+        }
+        parts
+            .extensions
+            .get::<Tenant>()
+            .cloned()
+            .ok_or_else(|| ApiError {
+                status: StatusCode::BAD_REQUEST,
+                title: "Missing Tenant".to_string(),
+                ..Default::default()
+            })
+    }
+}
+
+/// Pooled connection scoped to the request's [`Tenant`], see
+/// [`TenantIsolation`]
+pub struct TenantDatabase {
+    conn: Object<AsyncPgConnection>,
+    tenant: Tenant,
+}
+
+impl TenantDatabase {
+    pub fn conn(&mut self) -> &mut AsyncPgConnection {
+        &mut self.conn
+    }
+
+    pub fn tenant(&self) -> &Tenant {
+        &self.tenant
+    }
+}
+
+impl OperationInput for TenantDatabase {}
+impl<S: Sync> FromRequestParts<S> for TenantDatabase {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let tenant = Tenant::from_request_parts(parts, state).await?;
+        let tenancy = parts
+            .extensions
+            .get::<Arc<Tenancy>>()
+            .cloned()
+            .ok_or_else(not_configured)?;
+        let mut conn = tenancy.pool.get().await.map_err(|e| {
+            tracing::error!("Error checking out a connection: {:?}", e);
+            ApiError {
+                status: StatusCode::SERVICE_UNAVAILABLE,
+                title: "Database Unavailable".to_string(),
+                ..Default::default()
+            }
+        })?;
+        // Every checkout sets the scope anew, a connection never keeps the
+        // tenant of a previous request
+        let (setting, value) = match &tenancy.isolation {
+            TenantIsolation::Schema { prefix } => (
+                "search_path".to_string(),
+                format!("\"{prefix}{}\"", tenant.0),
+            ),
+            TenantIsolation::SessionVariable(name) => (name.clone(), tenant.0.clone()),
+        };
+        let scoped = diesel::sql_query("SELECT set_config($1, $2, false)")
+            .bind::<Text, _>(setting)
+            .bind::<Text, _>(value)
+            .execute(&mut *conn)
+            .await;
+        if let Err(err) = scoped {
+            // Don't hand a connection in an unknown scope back to the pool
+            let _ = Object::take(conn);
+            Err(err)?;
+        }
+        Ok(Self { conn, tenant })
+    }
+}
+
+fn not_configured() -> ApiError {
+    ApiError {
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        title: "Tenancy Not Configured".to_string(),
+        ..Default::default()
+    }
+}
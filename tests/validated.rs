@@ -0,0 +1,34 @@
+use {axum_api::prelude::*, schemars::schema_for, serde_json::json};
+
+#[validated]
+struct Order {
+    #[validate(range(exclusive_min = 0))]
+    quantity: i32,
+    #[validate(range(exclusive_max = 1.5))]
+    discount: f64,
+    #[validate(does_not_contain(pattern = "a.b"))]
+    note: String,
+}
+
+#[test]
+fn rules_are_mirrored_in_the_schema() {
+    let schema = schema_for!(Order).to_value();
+    let properties = &schema["properties"];
+    assert_eq!(properties["quantity"]["exclusiveMinimum"], 0);
+    assert_eq!(properties["discount"]["exclusiveMaximum"], 1.5);
+    assert_eq!(properties["note"]["not"], json!({ "pattern": r"a\.b" }));
+}
+
+#[test]
+fn rules_are_validated() {
+    let order = |quantity, note: &str| Order {
+        quantity,
+        discount: 0.5,
+        note: note.to_string(),
+    };
+    assert!(order(1, "ab").validate().is_ok());
+    let errors = order(0, "a.b").validate().unwrap_err();
+    let failed = errors.field_errors();
+    assert!(failed.contains_key("quantity"));
+    assert!(failed.contains_key("note"));
+}